@@ -2,11 +2,24 @@ use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{
+    InstanceType, NumberValidation, Schema, SchemaObject, StringValidation, SubschemaValidation,
+};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
 
-#[derive(Deserialize, Debug)]
+use crate::validate;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct Named<T> {
+    /// Unique name
     pub name: Arc<String>,
 
     #[serde(flatten)]
@@ -29,7 +42,7 @@ impl<T> DerefMut for Named<T> {
 
 impl<T> Named<T> {
     pub fn name(&self) -> &str {
-        return &*self.name;
+        return &self.name;
     }
 
     pub fn take(self) -> (Named<()>, T) {
@@ -50,20 +63,140 @@ impl<T> Named<T> {
     }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct PipeSink {
+/// File permissions, given as octal digits (`0660` or `"0660"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u32),
+            String(String),
+        }
+
+        // Numbers like `0o660` are already parsed by YAML, while `0660` is read as a string of
+        // octal digits. Larger numbers are most likely octal digits written without the leading
+        // zero, like `660`, which would silently give wrong permissions.
+        let mode = match Raw::deserialize(deserializer)? {
+            Raw::Number(number) if number <= 0o777 => number,
+            Raw::Number(number) => {
+                return Err(D::Error::custom(format!(
+                    "invalid file mode: {} (write `0{}` or `0o{}` for an octal mode)",
+                    number, number, number
+                )));
+            }
+            Raw::String(string) => u32::from_str_radix(string.trim_start_matches("0o"), 8)
+                .map_err(|_| D::Error::custom(format!("invalid file mode: {}", string)))?,
+        };
+
+        if mode > 0o7777 {
+            return Err(D::Error::custom(format!("invalid file mode: {:o}", mode)));
+        }
+
+        return Ok(Self(mode));
+    }
+}
+
+impl JsonSchema for FileMode {
+    fn schema_name() -> String {
+        return "FileMode".to_string();
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let number = SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            number: Some(Box::new(NumberValidation {
+                minimum: Some(0.0),
+                maximum: Some(0o777 as f64),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        let string = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^(0o)?[0-7]{1,4}$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        return SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![number.into(), string.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into();
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Fifo {
+    /// Path of the FIFO
     pub path: PathBuf,
 
+    /// Create the FIFO if it does not exist
     #[serde(default)]
     pub create: bool,
+
+    /// Permissions of the FIFO
+    #[serde(default = "Fifo::default_mode")]
+    pub mode: FileMode,
+
+    /// User owning the FIFO, by name or ID
+    pub owner: Option<String>,
+
+    /// Group owning the FIFO, by name or ID
+    pub group: Option<String>,
+
+    /// Remove the FIFO on shutdown if it has been created
+    #[serde(default)]
+    pub remove: bool,
 }
 
-#[derive(Deserialize, Debug)]
+impl Fifo {
+    fn default_mode() -> FileMode {
+        return FileMode(0o660);
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PipeSink {
+    #[serde(flatten)]
+    pub fifo: Fifo,
+
+    /// Sample rate at which data is written to the pipe
+    #[serde(default = "PipeSink::default_rate")]
+    pub rate: u32,
+
+    /// Number of interleaved channels written to the pipe
+    #[serde(default = "PipeSink::default_channels")]
+    pub channels: u16,
+}
+
+impl PipeSink {
+    fn default_rate() -> u32 {
+        return 48000;
+    }
+
+    fn default_channels() -> u16 {
+        return 2;
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct DeviceSink {
+    /// Name of the sound device, as listed by `soundwire devices`
+    #[allow(unused)]
     pub device: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 #[serde(rename_all = "camelCase")]
 pub enum Sink {
@@ -71,20 +204,82 @@ pub enum Sink {
     Device(DeviceSink),
 }
 
-#[derive(Deserialize, Debug)]
-pub struct PipeSource {
-    pub path: PathBuf,
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Overflow {
+    /// Discard incoming samples which do not fit into the buffer
+    #[default]
+    DropNewest,
+
+    /// Discard the oldest buffered samples to make room for incoming ones
+    DropOldest,
+
+    /// Wait briefly for the sink to make room before discarding incoming samples
+    Block,
+}
 
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Output {
     #[serde(default)]
-    pub create: bool,
+    pub overflow: Overflow,
+
+    /// Delay applied to the output in milliseconds
+    #[serde(default)]
+    pub latency: u32,
+
+    /// Initial volume in percent
+    #[serde(default = "Output::default_volume")]
+    #[schemars(range(max = 100))]
+    pub volume: u8,
+
+    /// Initially mute the output
+    #[serde(default)]
+    pub muted: bool,
+
+    /// Name of the initially active source
+    pub source: Option<Arc<String>>,
+
+    #[serde(flatten)]
+    pub sink: Sink,
 }
 
-#[derive(Deserialize, Debug)]
+impl Output {
+    fn default_volume() -> u8 {
+        return 100;
+    }
+
+    /// Whether both describe the same output, ignoring the settings only applied on creation.
+    pub fn same_output(&self, other: &Self) -> bool {
+        return self.overflow == other.overflow
+            && self.latency == other.latency
+            && self.sink == other.sink;
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PipeSource {
+    #[serde(flatten)]
+    pub fifo: Fifo,
+
+    /// Number of interleaved channels read from the pipe
+    #[serde(default = "PipeSource::default_channels")]
+    pub channels: u16,
+}
+
+impl PipeSource {
+    fn default_channels() -> u16 {
+        return 2;
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 pub struct DeviceSource {
+    /// Name of the sound device, as listed by `soundwire devices`
+    #[allow(unused)]
     pub device: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 #[serde(rename_all = "camelCase")]
 pub enum Source {
@@ -92,22 +287,362 @@ pub enum Source {
     Device(DeviceSource),
 }
 
-#[derive(Deserialize, Debug)]
+/// Address the control server listens on.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Listen {
+    /// TCP address given as `host:port`
+    Tcp(String),
+
+    /// Unix domain socket
+    Unix(UnixSocket),
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+
+    /// Permissions of the socket
+    #[serde(default = "UnixSocket::default_mode")]
+    pub mode: FileMode,
+
+    /// User owning the socket, by name or ID
+    pub owner: Option<String>,
+
+    /// Group owning the socket, by name or ID
+    pub group: Option<String>,
+}
+
+impl UnixSocket {
+    fn default_mode() -> FileMode {
+        return FileMode(0o660);
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Control {
+    /// Addresses to accept control connections on
+    #[serde(default = "Control::default_listen")]
+    pub listen: Vec<Listen>,
+}
+
+impl Control {
+    fn default_listen() -> Vec<Listen> {
+        return vec![Listen::Tcp("[::]:1705".to_string())];
+    }
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        return Self {
+            listen: Self::default_listen(),
+        };
+    }
+}
+
+/// Configuration of soundwire.
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct Config {
-    pub outputs: Vec<Named<Sink>>,
+    /// Outputs playing one of the sources
+    #[serde(default)]
+    pub outputs: Vec<Named<Output>>,
+
+    /// Sources available to all outputs
+    #[serde(default)]
     pub sources: Vec<Named<Source>>,
+
+    /// File to keep the volume, mute state and active source of outputs across restarts
+    pub state: Option<PathBuf>,
+
+    /// Settings of the control server
+    #[serde(default)]
+    pub control: Control,
+
+    /// Files with additional outputs and sources, given as glob patterns relative to the config
+    /// file
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+/// Additional outputs and sources read from an included file.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Include {
+    #[serde(default)]
+    outputs: Vec<Named<Output>>,
+
+    #[serde(default)]
+    sources: Vec<Named<Source>>,
 }
 
 impl Config {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let mut f = std::fs::OpenOptions::new()
-            .read(true)
-            .open(path.as_ref())
-            .with_context(|| format!("Failed to open config file: {}", path.as_ref().display()))?;
+        let path = path.as_ref();
+
+        let mut origin = Origin::default();
+
+        let mut config: Self = origin.read(path)?;
+        origin
+            .outputs
+            .extend((0..config.outputs.len()).map(|i| (0, i)));
+        origin
+            .sources
+            .extend((0..config.sources.len()).map(|i| (0, i)));
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for pattern in config.include.iter() {
+            for path in resolve(base, pattern)? {
+                let file = origin.files.len();
+                let include: Include = origin.read(&path)?;
 
-        let config: Self = serde_yaml::from_reader(&mut f)
-            .with_context(|| format!("Failed to parse config file: {}", path.as_ref().display()))?;
+                origin
+                    .outputs
+                    .extend((0..include.outputs.len()).map(|i| (file, i)));
+                origin
+                    .sources
+                    .extend((0..include.sources.len()).map(|i| (file, i)));
+
+                config.outputs.extend(include.outputs);
+                config.sources.extend(include.sources);
+            }
+        }
+
+        let problems = validate::validate(&config, &origin);
+        if !problems.is_empty() {
+            let problems = problems
+                .iter()
+                .map(|problem| format!("  {}", problem))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            bail!("Invalid config file: {}\n{}", path.display(), problems);
+        }
 
         return Ok(config);
     }
 }
+
+/// Expands an include pattern to the matching files in alphabetical order.
+fn resolve(base: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let full = base.join(pattern);
+    let full = full.to_string_lossy();
+
+    let mut paths = glob::glob(&full)
+        .with_context(|| format!("Invalid include pattern: {}", pattern))?
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    // Patterns without wildcards name a single file which must exist
+    if paths.is_empty() && glob::Pattern::escape(&full) == full {
+        bail!("Included file not found: {}", full);
+    }
+
+    return Ok(paths);
+}
+
+/// The files a config has been read from, used to locate problems.
+#[derive(Default)]
+pub struct Origin {
+    /// Path and text of each file, starting with the main config file
+    pub files: Vec<(PathBuf, String)>,
+
+    /// File and index within that file of each output
+    pub outputs: Vec<(usize, usize)>,
+
+    /// File and index within that file of each source
+    pub sources: Vec<(usize, usize)>,
+}
+
+impl Origin {
+    fn read<T: DeserializeOwned>(&mut self, path: &Path) -> Result<T> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to open config file: {}", path.display()))?;
+
+        let mut value: Value = serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+        substitute(&mut value, &|name| std::env::var(name).ok())
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+        let value = serde_yaml::from_value(value)
+            .map_err(|err| {
+                // Errors from parsed values lack their location, which is known when the same
+                // error occurs for the raw text
+                return match serde_yaml::from_str::<T>(&text) {
+                    Err(located) if located.to_string().contains(&err.to_string()) => located,
+                    _ => err,
+                };
+            })
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+        self.files.push((path.to_path_buf(), text));
+
+        return Ok(value);
+    }
+}
+
+lazy_static! {
+    /// Reference to an environment variable, or an escaped one starting with `$$`
+    static ref VARIABLE: Regex =
+        Regex::new(r"\$(\$)?\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").expect("valid regex");
+}
+
+/// Replaces `${NAME}` in all strings of the document with the value of the variable `NAME`.
+///
+/// A default for unset variables can be given as `${NAME:-default}` and `$${NAME}` is kept as a
+/// literal `${NAME}`. As this works on parsed strings, values are taken as they are.
+fn substitute(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    let mut missing = Vec::new();
+    expand(value, lookup, &mut missing);
+
+    if !missing.is_empty() {
+        bail!("Environment variables not set: {}", missing.join(", "));
+    }
+
+    return Ok(());
+}
+
+fn expand(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>, missing: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            let expanded = VARIABLE.replace_all(text, |captures: &Captures| {
+                if captures.get(1).is_some() {
+                    return captures[0][1..].to_string();
+                }
+
+                let name = &captures[2];
+                return match (lookup(name), captures.get(3)) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => default.as_str().to_string(),
+                    (None, None) => {
+                        missing.push(name.to_string());
+                        String::new()
+                    }
+                };
+            });
+
+            *text = expanded.into_owned();
+        }
+
+        Value::Sequence(values) => {
+            for value in values.iter_mut() {
+                expand(value, lookup, missing);
+            }
+        }
+
+        Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                expand(value, lookup, missing);
+            }
+        }
+
+        Value::Tagged(tagged) => expand(&mut tagged.value, lookup, missing),
+
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(yaml: &str) -> Result<u32, serde_yaml::Error> {
+        return serde_yaml::from_str::<FileMode>(yaml).map(|mode| mode.0);
+    }
+
+    #[test]
+    fn file_mode() {
+        assert_eq!(mode("0o660").unwrap(), 0o660);
+        assert_eq!(mode("0660").unwrap(), 0o660);
+        assert_eq!(mode("\"0660\"").unwrap(), 0o660);
+        assert_eq!(mode("\"0o660\"").unwrap(), 0o660);
+        assert_eq!(mode("\"2770\"").unwrap(), 0o2770);
+
+        // Octal digits without leading zero must not be taken as decimal
+        assert!(mode("660").is_err());
+        assert!(mode("644").is_err());
+        assert!(mode("600").is_err());
+
+        assert!(mode("\"0680\"").is_err());
+        assert!(mode("0o17777").is_err());
+        assert!(mode("\"17777\"").is_err());
+    }
+
+    /// Substitutes variables in the document using fixed values instead of the environment.
+    fn substituted(text: &str) -> Result<Value> {
+        let lookup = |name: &str| -> Option<String> {
+            return match name {
+                "NAME" => Some("Kitchen".to_string()),
+                "VALUE" => Some("it's \"quoted\" # not: a comment\n[x]".to_string()),
+                _ => None,
+            };
+        };
+
+        let mut value: Value = serde_yaml::from_str(text).unwrap();
+        substitute(&mut value, &lookup)?;
+
+        return Ok(value);
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let value = substituted(
+            "\
+outputs:
+  - name: ${NAME}
+    path: /run/${UNSET:-default}/${NAME}
+    volume: 50
+",
+        )
+        .unwrap();
+
+        assert_eq!(value["outputs"][0]["name"], "Kitchen");
+        assert_eq!(value["outputs"][0]["path"], "/run/default/Kitchen");
+        assert_eq!(value["outputs"][0]["volume"], 50);
+
+        assert_eq!(substituted("name: $${NAME}").unwrap()["name"], "${NAME}");
+
+        let err = substituted("name: ${UNSET}\npath: ${OTHER}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment variables not set: UNSET, OTHER"
+        );
+    }
+
+    #[test]
+    fn substitute_takes_values_as_they_are() {
+        let expected = "it's \"quoted\" # not: a comment\n[x]";
+
+        assert_eq!(substituted("name: ${VALUE}").unwrap()["name"], expected);
+        assert_eq!(substituted("name: '${VALUE}'").unwrap()["name"], expected);
+        assert_eq!(substituted("name: \"${VALUE}\"").unwrap()["name"], expected);
+
+        // Comments and block scalars are not affected
+        let value =
+            substituted("# ${UNSET}\nnote: |\n  'hello ${NAME}\npath: ${VALUE} # ${UNSET}\n")
+                .unwrap();
+        assert_eq!(value["note"], "'hello Kitchen\n");
+        assert_eq!(value["path"], expected);
+    }
+
+    #[test]
+    fn resolve_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.yaml", "a.yaml", "c.txt"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        assert_eq!(
+            resolve(dir.path(), "*.yaml").unwrap(),
+            vec![dir.path().join("a.yaml"), dir.path().join("b.yaml")]
+        );
+        assert_eq!(
+            resolve(dir.path(), "c.txt").unwrap(),
+            vec![dir.path().join("c.txt")]
+        );
+
+        // Only patterns without wildcards must match
+        assert!(resolve(dir.path(), "*.conf").unwrap().is_empty());
+        assert!(resolve(dir.path(), "d.yaml").is_err());
+    }
+}
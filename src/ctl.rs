@@ -0,0 +1,430 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use futures::SinkExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use structopt::StructOpt;
+use tokio::net::{TcpStream, UnixStream};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+use crate::proto::{types, Connection, Request, Response, ResponseData, WithId};
+
+#[derive(StructOpt, Debug)]
+pub struct Opt {
+    /// Address of the control server, either `host:port` or the path of a unix socket, optionally
+    /// prefixed with `unix:`
+    #[structopt(short = "a", long = "address", default_value = "localhost:1705")]
+    address: String,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// List all outputs and sources
+    List,
+
+    /// Set the volume of an output in percent
+    Volume { output: String, percent: f32 },
+
+    /// Mute an output
+    Mute { output: String },
+
+    /// Unmute an output
+    Unmute { output: String },
+
+    /// Switch the source played by an output
+    Switch { output: String, source: String },
+
+    /// Print notifications sent by the server until interrupted
+    Watch,
+}
+
+/// A message received from the server.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Message {
+    Response(Response),
+    Notification(Request),
+}
+
+/// Returns the socket path if the address is not a TCP address.
+///
+/// Addresses are taken as paths if prefixed with `unix:`, if they exist or if they do not end in a
+/// port.
+fn unix_path(address: &str) -> Option<&str> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        return Some(path);
+    }
+
+    if address.contains('/') || Path::new(address).exists() {
+        return Some(address);
+    }
+
+    return match address.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => None,
+        _ => Some(address),
+    };
+}
+
+/// Client for the control protocol.
+pub struct Client {
+    lines: Framed<Box<dyn Connection>, LinesCodec>,
+    next_id: usize,
+}
+
+impl Client {
+    pub fn new(stream: Box<dyn Connection>) -> Self {
+        return Self {
+            lines: Framed::new(stream, LinesCodec::new()),
+            next_id: 0,
+        };
+    }
+
+    /// Connects to a TCP address or, if the address is a path, to a unix socket.
+    pub async fn connect(address: &str) -> Result<Self> {
+        let stream: Box<dyn Connection> = if let Some(path) = unix_path(address) {
+            Box::new(
+                UnixStream::connect(path)
+                    .await
+                    .with_context(|| format!("Failed to connect to: {}", address))?,
+            )
+        } else {
+            Box::new(
+                TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("Failed to connect to: {}", address))?,
+            )
+        };
+
+        return Ok(Self::new(stream));
+    }
+
+    /// Calls a method and waits for its result.
+    ///
+    /// Notifications received while waiting are dropped.
+    pub async fn call<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: P,
+    ) -> Result<R> {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+
+        let request = Request::new(Some(id.clone()), method, params)?;
+        self.lines.send(serde_json::to_string(&request)?).await?;
+
+        loop {
+            let Message::Response(response) = self.receive().await? else {
+                continue;
+            };
+
+            // Errors without ID are caused by requests the server could not parse
+            if response.id.as_ref().is_some_and(|other| other != &id) {
+                continue;
+            }
+
+            return match response.data {
+                ResponseData::Result(value) => serde_json::from_value(value)
+                    .with_context(|| format!("Invalid result of {}", method)),
+                ResponseData::Error(err) => bail!("{} failed: {}", method, err.message),
+            };
+        }
+    }
+
+    /// Waits for the next notification.
+    pub async fn notification(&mut self) -> Result<Request> {
+        loop {
+            if let Message::Notification(notification) = self.receive().await? {
+                return Ok(notification);
+            }
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Message> {
+        let line = self
+            .lines
+            .next()
+            .await
+            .context("Connection closed by server")??;
+
+        return serde_json::from_str(&line).with_context(|| format!("Invalid message: {}", line));
+    }
+}
+
+fn with_id<I>(id: &str, inner: I) -> WithId<I> {
+    return WithId {
+        id: id.to_string(),
+        inner,
+    };
+}
+
+pub async fn run(opt: Opt) -> Result<()> {
+    let mut client = Client::connect(&opt.address).await?;
+
+    match opt.command {
+        Command::List => {
+            let mut status: types::Server =
+                client.call("Server.GetStatus", types::Empty {}).await?;
+
+            status.groups.sort_by(|a, b| a.name.cmp(&b.name));
+            status.streams.sort_by(|a, b| a.stream_id.cmp(&b.stream_id));
+
+            println!("Outputs:");
+            for group in status.groups.iter() {
+                let volume = group
+                    .clients
+                    .first()
+                    .map(|client| client.config.volume.percent)
+                    .unwrap_or_default();
+
+                let source = if group.stream_id.is_empty() {
+                    "nothing"
+                } else {
+                    group.stream_id.as_str()
+                };
+
+                println!(
+                    "  {}: {:.0}%{}, playing {}",
+                    group.name,
+                    volume,
+                    if group.muted { " (muted)" } else { "" },
+                    source
+                );
+            }
+
+            println!("Sources:");
+            for stream in status.streams.iter() {
+                let status = match stream.status {
+                    types::StreamStatus::Idle => "idle",
+                    types::StreamStatus::Active => "active",
+                };
+
+                println!("  {}: {}", stream.stream_id, status);
+            }
+        }
+
+        Command::Volume { output, percent } => {
+            if !(0.0..=100.0).contains(&percent) {
+                bail!("Volume must be between 0 and 100: {}", percent);
+            }
+
+            // Setting the volume also sets the mute state, which must be kept
+            let status: types::Client = client
+                .call("Client.GetStatus", with_id(&output, types::Empty {}))
+                .await?;
+
+            let volume = types::Volume {
+                muted: status.config.volume.muted,
+                percent,
+            };
+
+            let _: types::Volume = client
+                .call("Client.SetVolume", with_id(&output, volume))
+                .await?;
+        }
+
+        Command::Mute { output } => {
+            let _: types::Mute = client
+                .call(
+                    "Group.SetMute",
+                    with_id(&output, types::Mute { mute: true }),
+                )
+                .await?;
+        }
+
+        Command::Unmute { output } => {
+            let _: types::Mute = client
+                .call(
+                    "Group.SetMute",
+                    with_id(&output, types::Mute { mute: false }),
+                )
+                .await?;
+        }
+
+        Command::Switch { output, source } => {
+            let stream = types::StreamId {
+                stream_id: Arc::new(source),
+            };
+
+            let _: types::Stream = client
+                .call("Group.SetStream", with_id(&output, stream))
+                .await?;
+        }
+
+        Command::Watch => loop {
+            let notification = client.notification().await?;
+            println!(
+                "{} {}",
+                notification.method,
+                Value::Object(notification.params)
+            );
+        },
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::{process, Shared, State};
+    use crate::routing;
+
+    /// Connects a client to the control server running in-process.
+    fn connect(shared: &Arc<Mutex<Shared>>, token: &CancellationToken) -> Client {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(process(shared.clone(), Box::new(server), token.clone()));
+
+        return Client::new(Box::new(client));
+    }
+
+    async fn notification(client: &mut Client) -> Request {
+        return tokio::time::timeout(Duration::from_secs(5), client.notification())
+            .await
+            .expect("Notification received")
+            .expect("Valid notification");
+    }
+
+    #[test]
+    fn address_kinds() {
+        assert_eq!(unix_path("localhost:1705"), None);
+        assert_eq!(unix_path("[::1]:1705"), None);
+
+        assert_eq!(unix_path("control.sock"), Some("control.sock"));
+        assert_eq!(unix_path("./control:1705"), Some("./control:1705"));
+        assert_eq!(unix_path("unix:control:1705"), Some("control:1705"));
+    }
+
+    #[tokio::test]
+    async fn control() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+
+        let config: Config = serde_yaml::from_str(&format!(
+            "\
+outputs:
+  - name: out
+    type: pipe
+    path: {}
+    create: true
+    source: a
+sources:
+  - name: a
+    type: pipe
+    path: {}
+    create: true
+  - name: b
+    type: pipe
+    path: {}
+    create: true
+",
+            path("out"),
+            path("a"),
+            path("b")
+        ))
+        .unwrap();
+
+        let mut state = State::default();
+        for output in config.outputs {
+            routing::add_sink(&mut state, output).unwrap();
+        }
+        for source in config.sources {
+            routing::add_source(&mut state, source).unwrap();
+        }
+
+        let token = CancellationToken::new();
+        let shared = Arc::new(Mutex::new(Shared::new(Arc::new(Mutex::new(state))).await));
+
+        let mut client = connect(&shared, &token);
+        let mut watcher = connect(&shared, &token);
+
+        let mut status: types::Server = client
+            .call("Server.GetStatus", types::Empty {})
+            .await
+            .unwrap();
+        status.streams.sort_by(|a, b| a.stream_id.cmp(&b.stream_id));
+
+        assert_eq!(status.groups.len(), 1);
+        assert_eq!(status.groups[0].name.as_str(), "out");
+        assert_eq!(status.groups[0].stream_id.as_str(), "a");
+        assert_eq!(
+            status
+                .streams
+                .iter()
+                .map(|stream| stream.stream_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        let volume: types::Volume = client
+            .call(
+                "Client.SetVolume",
+                with_id(
+                    "out",
+                    types::Volume {
+                        muted: false,
+                        percent: 40.0,
+                    },
+                ),
+            )
+            .await
+            .unwrap();
+        assert!((volume.percent - 40.0).abs() < 1.0);
+
+        let changed = notification(&mut watcher).await;
+        assert_eq!(changed.method, "Client.OnVolumeChanged");
+        assert_eq!(changed.params["id"], "out");
+
+        let _: types::Stream = client
+            .call(
+                "Group.SetStream",
+                with_id(
+                    "out",
+                    types::StreamId {
+                        stream_id: Arc::new("b".to_string()),
+                    },
+                ),
+            )
+            .await
+            .unwrap();
+
+        let changed = notification(&mut watcher).await;
+        assert_eq!(changed.method, "Group.OnStreamChanged");
+        assert_eq!(changed.params["stream_id"], "b");
+
+        let status: types::Server = client
+            .call("Server.GetStatus", types::Empty {})
+            .await
+            .unwrap();
+        assert_eq!(status.groups[0].stream_id.as_str(), "b");
+
+        // Unknown outputs are reported as errors
+        let result: Result<types::Volume> = client
+            .call(
+                "Client.SetVolume",
+                with_id(
+                    "missing",
+                    types::Volume {
+                        muted: false,
+                        percent: 40.0,
+                    },
+                ),
+            )
+            .await;
+        assert!(result.is_err());
+
+        token.cancel();
+    }
+}
@@ -1,15 +1,19 @@
+use std::fmt;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Stream, StreamConfig};
+use cpal::{StreamConfig, SupportedStreamConfigRange};
+use futures::future::BoxFuture;
 use lazy_static::lazy_static;
-use ringbuf::HeapConsumer;
-use tracing::error;
+use serde::Serialize;
 
 use crate::config;
-use crate::sink::{SinkStream, SinkType};
+use crate::sink::{SharedReceiver, SinkStream, SinkType};
 use crate::source::{SourceCallback, SourceStream, SourceType};
+use crate::supervisor::Reporter;
 
 lazy_static! {
     static ref HOST: cpal::Host = cpal::default_host();
@@ -17,71 +21,273 @@ lazy_static! {
 
 pub struct Device;
 
-impl SourceStream for Stream {}
+/// Audio devices of the host used for device sources and outputs, as listed by the `devices`
+/// subcommand.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HostInfo {
+    pub name: String,
 
-impl SinkStream for Stream {}
+    pub inputs: Vec<DeviceInfo>,
+    pub outputs: Vec<DeviceInfo>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub name: String,
+
+    /// Whether this is the default device of the host
+    pub default: bool,
+
+    pub configs: Vec<ConfigInfo>,
+}
+
+/// A range of sample rates supported with a sample format and channel count.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigInfo {
+    pub channels: u16,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub format: String,
+}
+
+impl From<SupportedStreamConfigRange> for ConfigInfo {
+    fn from(config: SupportedStreamConfigRange) -> Self {
+        return Self {
+            channels: config.channels(),
+            min_rate: config.min_sample_rate().0,
+            max_rate: config.max_sample_rate().0,
+            format: config.sample_format().to_string(),
+        };
+    }
+}
+
+impl fmt::Display for HostInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+
+        for (kind, devices) in [("Inputs", &self.inputs), ("Outputs", &self.outputs)] {
+            writeln!(f, "  {}:", kind)?;
+
+            if devices.is_empty() {
+                writeln!(f, "    none")?;
+            }
+
+            for device in devices {
+                write!(f, "    {}", device.name)?;
+                if device.default {
+                    write!(f, " (default)")?;
+                }
+                writeln!(f)?;
+
+                for config in device.configs.iter() {
+                    writeln!(
+                        f,
+                        "      {} channels, {}, {} - {} Hz",
+                        config.channels, config.format, config.min_rate, config.max_rate
+                    )?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Lists the input and output devices of the default host.
+pub fn list() -> Result<HostInfo> {
+    let default_input = HOST.default_input_device().and_then(|d| d.name().ok());
+    let default_output = HOST.default_output_device().and_then(|d| d.name().ok());
+
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+
+    for device in HOST.devices()? {
+        let Ok(name) = device.name() else {
+            continue;
+        };
+
+        let configs = device
+            .supported_input_configs()
+            .map(|configs| configs.map(ConfigInfo::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if !configs.is_empty() {
+            inputs.push(DeviceInfo {
+                default: default_input.as_ref() == Some(&name),
+                name: name.clone(),
+                configs,
+            });
+        }
+
+        let configs = device
+            .supported_output_configs()
+            .map(|configs| configs.map(ConfigInfo::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if !configs.is_empty() {
+            outputs.push(DeviceInfo {
+                default: default_output.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+    }
+
+    return Ok(HostInfo {
+        name: HOST.id().name().to_string(),
+        inputs,
+        outputs,
+    });
+}
+
+/// Keeps a cpal stream alive on a dedicated thread, as cpal streams can not be moved between
+/// threads.
+pub struct DeviceStream {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceStream {
+    /// Builds the stream on its thread.
+    ///
+    /// Opening a device can take a while, so this does not wait for the stream to be built. A
+    /// failure is reported to the supervisor instead.
+    fn spawn(
+        reporter: Reporter,
+        build: impl FnOnce() -> Result<cpal::Stream> + Send + 'static,
+    ) -> Result<Self> {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread = std::thread::spawn(move || {
+            let stream = match build() {
+                Ok(stream) => stream,
+                Err(err) => {
+                    reporter.fail(err);
+                    return;
+                }
+            };
+
+            // Keep the stream until the stop sender is dropped
+            let _ = stop_rx.recv();
+
+            drop(stream);
+        });
+
+        return Ok(Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        });
+    }
+}
+
+impl Drop for DeviceStream {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl DeviceStream {
+    fn shutdown(self: Box<Self>) -> BoxFuture<'static, ()> {
+        // Joining the stream thread blocks
+        return Box::pin(async move {
+            let _ = tokio::task::spawn_blocking(move || drop(self)).await;
+        });
+    }
+}
+
+impl SourceStream for DeviceStream {
+    fn stop(self: Box<Self>) -> BoxFuture<'static, ()> {
+        return self.shutdown();
+    }
+}
+
+impl SinkStream for DeviceStream {
+    fn stop(self: Box<Self>) -> BoxFuture<'static, ()> {
+        return self.shutdown();
+    }
+}
 
 impl SourceType for Device {
     type Config = config::DeviceSource;
 
-    type Stream = Stream;
+    type Stream = DeviceStream;
 
     fn source(
+        &self,
         _name: &str,
-        _config: Self::Config,
+        _config: &Self::Config,
         mut callback: impl SourceCallback + 'static,
+        reporter: Reporter,
     ) -> Result<Self::Stream> {
-        let device = HOST
-            .default_input_device() // TODO: search for configured device
-            .context("No default input device")?;
-
-        let config: StreamConfig = device.default_input_config()?.into();
-
-        let stream = device.build_input_stream(
-            &config,
-            move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                callback.data(data);
-            },
-            |err: cpal::StreamError| {
-                error!("Device input stream error: {}", err);
-            },
-            Some(Duration::from_millis(100)),
-        )?;
-
-        stream.play()?;
-
-        return Ok(stream);
+        return DeviceStream::spawn(reporter.clone(), move || {
+            let device = HOST
+                .default_input_device() // TODO: search for configured device
+                .context("No default input device")?;
+
+            let config: StreamConfig = device.default_input_config()?.into();
+
+            let stream = device.build_input_stream(
+                &config,
+                move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                    callback.data(data);
+                },
+                move |err: cpal::StreamError| {
+                    reporter.fail(format!("Device input stream error: {}", err));
+                },
+                Some(Duration::from_millis(100)),
+            )?;
+
+            stream.play()?;
+
+            return Ok(stream);
+        });
     }
 }
 
 impl SinkType for Device {
     type Config = config::DeviceSink;
-    type Stream = Stream;
-
-    fn sink(_name: &str, _config: Self::Config, mut rx: HeapConsumer<i16>) -> Result<Self::Stream> {
-        let device = HOST
-            .default_output_device() // TODO: search for configured device
-            .context("No default output device")?;
-
-        let config: StreamConfig = device.default_output_config()?.into();
-
-        let stream = device.build_output_stream(
-            &config,
-            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                let r = rx.pop_slice(data);
-                if r < data.len() {
-                    data[r..].fill(0i16);
-                    // eprintln!("Output underflow");
-                }
-            },
-            |err: cpal::StreamError| {
-                error!("Device output stream error: {}", err);
-            },
-            Some(Duration::from_millis(100)),
-        )?;
+    type Stream = DeviceStream;
+
+    fn sink(
+        &self,
+        _name: &str,
+        _config: &Self::Config,
+        rx: SharedReceiver,
+        reporter: Reporter,
+    ) -> Result<Self::Stream> {
+        return DeviceStream::spawn(reporter.clone(), move || {
+            let device = HOST
+                .default_output_device() // TODO: search for configured device
+                .context("No default output device")?;
+
+            let config: StreamConfig = device.default_output_config()?.into();
+
+            rx.blocking_lock()
+                .set_format(config.sample_rate.0, config.channels);
+
+            let stream = device.build_output_stream(
+                &config,
+                move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                    // Never block the audio thread - play silence while the receiver is taken
+                    match rx.try_lock() {
+                        Ok(mut rx) => rx.fill(data),
+                        Err(_) => data.fill(0i16),
+                    }
+                },
+                move |err: cpal::StreamError| {
+                    reporter.fail(format!("Device output stream error: {}", err));
+                },
+                Some(Duration::from_millis(100)),
+            )?;
 
-        stream.play()?;
+            stream.play()?;
 
-        return Ok(stream);
+            return Ok(stream);
+        });
     }
 }
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+/// Number of events kept for slow subscribers, which miss older events once exceeded
+const CAPACITY: usize = 256;
+
+/// A change of the runtime state of a sink or source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The volume of a sink has been changed
+    Volume { sink: Arc<String>, volume: u8 },
+
+    /// A sink has been muted or unmuted
+    Muted { sink: Arc<String>, muted: bool },
+
+    /// A sink has switched to another source, or to none
+    Switched {
+        sink: Arc<String>,
+        source: Option<Arc<String>>,
+    },
+
+    /// A source has started or stopped delivering data
+    Activity { source: Arc<String>, active: bool },
+}
+
+/// Distributes events to all subscribers.
+///
+/// Clones share the same subscribers.
+#[derive(Debug, Clone)]
+pub struct Events {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        return Self { tx };
+    }
+}
+
+impl Events {
+    pub fn emit(&self, event: Event) {
+        // Sending only fails if there are no subscribers
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        return self.tx.subscribe();
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write as _};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::device;
+
+/// Pipe sources added as examples to a new config
+const SOURCES: &[(&str, &str)] = &[
+    ("mopidy", "/run/soundwire/mopidy"),
+    ("pulseaudio", "/run/soundwire/pulseaudio"),
+];
+
+/// Writes a starter config with one output per output device of the default host.
+pub fn run(path: &Path, force: bool) -> Result<()> {
+    let host = device::list()?;
+    let devices = host
+        .outputs
+        .iter()
+        .map(|device| device.name.as_str())
+        .collect::<Vec<_>>();
+
+    let text = render(&devices);
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    let mut f = match options.open(path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            bail!(
+                "Config file already exists: {} (use --force to overwrite)",
+                path.display()
+            );
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to create config file: {}", path.display()))
+        }
+    };
+
+    f.write_all(text.as_bytes())
+        .with_context(|| format!("Failed to write config file: {}", path.display()))?;
+
+    println!(
+        "Written config file with {} outputs: {}",
+        devices.len(),
+        path.display()
+    );
+
+    return Ok(());
+}
+
+/// Quotes a string for use as a YAML scalar.
+fn quote(s: &str) -> String {
+    // JSON strings are valid YAML, but keep `${` from being taken as variable
+    return serde_json::to_string(&s.replace("${", "$${")).expect("string serializes");
+}
+
+fn render(devices: &[&str]) -> String {
+    let mut text = String::new();
+
+    // Writing to a string never fails
+    let mut line = |s: &str| {
+        let _ = writeln!(text, "{}", s);
+    };
+
+    line("# Configuration of soundwire, generated by `soundwire init`.");
+    line("# Run `soundwire --check` to validate changes and `soundwire devices` to list the");
+    line("# available sound devices.");
+    line("");
+
+    if devices.is_empty() {
+        line("# No output devices found - add outputs like this:");
+        line("#    - name: Speakers");
+        line("#      type: device");
+        line("#      device: Name of the sound device");
+        line("outputs: []");
+    } else {
+        line("outputs:");
+
+        let mut names = HashSet::new();
+        for device in devices {
+            // Output names must be unique, while device names may repeat
+            let mut name = device.to_string();
+            let mut n = 1;
+            while !names.insert(name.clone()) {
+                n += 1;
+                name = format!("{} {}", device, n);
+            }
+
+            line(&format!("    - name: {}", quote(&name)));
+            line("      type: device");
+            line(&format!("      device: {}", quote(device)));
+            line("      # Initial volume in percent and mute state");
+            line("      # volume: 100");
+            line("      # muted: false");
+            line("      # Name of the initially played source");
+            line(&format!("      # source: {}", SOURCES[0].0));
+        }
+    }
+
+    line("");
+    line("# Raw audio written to these FIFOs (16 bit signed native endian samples, 2 channels)");
+    line("# is played on the outputs, e.g. by a music player.");
+    line("sources:");
+
+    for (name, path) in SOURCES {
+        line(&format!("    - name: {}", name));
+        line("      type: pipe");
+        line(&format!("      path: {}", path));
+        line("      create: true");
+    }
+
+    line("");
+    line("# Keep volume, mute state and active source of outputs across restarts");
+    line("# state: /var/lib/soundwire/state.json");
+    line("");
+    line("# control:");
+    line("#     listen:");
+    line("#         - \"[::]:1705\"");
+
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{Config, Sink};
+
+    #[test]
+    fn render_loads() {
+        let devices = ["Speakers", "Speakers", "we${ird}: #\"x' $${y}"];
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("soundwire.conf");
+        std::fs::write(&path, render(&devices)).unwrap();
+
+        // Loading substitutes variables and validates the config
+        let config = Config::load(&path).unwrap();
+
+        let outputs = config
+            .outputs
+            .iter()
+            .map(|output| match &output.sink {
+                Sink::Device(sink) => (output.name.as_str(), sink.device.as_str()),
+                sink => panic!("unexpected sink: {:?}", sink),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            outputs,
+            vec![
+                ("Speakers", "Speakers"),
+                ("Speakers 2", "Speakers"),
+                (devices[2], devices[2]),
+            ]
+        );
+    }
+}
@@ -1,30 +1,48 @@
-#![feature(trait_upcasting)]
+#![allow(clippy::needless_return)]
 
-use std::any::Any;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use futures::future::join_all;
 use structopt::StructOpt;
-use tracing::{info, Level};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn, Level};
 
 use crate::config::Config;
-use crate::proto::State;
-use crate::sink::{Sender, Sink};
-use crate::source::{Source, SourceCallback};
-use crate::switcher::Port;
+use crate::persist::Snapshot;
+use crate::proto::{Listener, State};
 
 mod config;
+mod events;
 mod sink;
 mod source;
+mod validate;
 
+mod supervisor;
 mod switcher;
 
 mod proto;
+mod routing;
 
 mod device;
 mod pipe;
 
+mod persist;
+mod systemd;
+
+mod ctl;
+mod init;
+
+/// Duration of the fade out of all sinks on shutdown
+const FADE_DURATION: Duration = Duration::from_millis(500);
+
+/// Maximum time to wait for sources and sinks to stop
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "soundwire", about = "audio routing daemon")]
 pub struct Opt {
@@ -33,9 +51,39 @@ pub struct Opt {
 
     #[structopt(short = "c", long = "config", default_value = "soundwire.conf")]
     config: PathBuf,
+
+    /// Validate the config file and exit
+    #[structopt(long = "check")]
+    check: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main(flavor = "current_thread")]
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Print the JSON Schema of the config file
+    Schema,
+
+    /// List the audio devices of the default host and their capabilities
+    Devices {
+        /// Print the devices as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Write a starter config file with outputs for the available audio devices
+    Init {
+        /// Overwrite an existing config file
+        #[structopt(long = "force")]
+        force: bool,
+    },
+
+    /// Control a running daemon
+    Ctl(ctl::Opt),
+}
+
+#[tokio::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
 
@@ -48,65 +96,170 @@ async fn main() -> Result<()> {
         })
         .init();
 
+    match opt.command {
+        Some(Command::Schema) => {
+            let schema = schemars::schema_for!(Config);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        Some(Command::Devices { json }) => {
+            let host = device::list()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&host)?);
+            } else {
+                print!("{}", host);
+            }
+            return Ok(());
+        }
+        Some(Command::Init { force }) => return init::run(&opt.config, force),
+        Some(Command::Ctl(opt)) => return ctl::run(opt).await,
+        None => {}
+    }
+
     let config = Config::load(&opt.config)
         .with_context(|| format!("Failed to load config: {}", opt.config.display()))?;
 
+    if opt.check {
+        println!("Config is valid: {}", opt.config.display());
+        return Ok(());
+    }
+
     info!("Welcome to SoundWire!");
 
-    let mut sinks = HashMap::new();
-    let mut sources = HashMap::new();
+    // Take the passed socket early, as this clears the related environment variables
+    let activated = systemd::listeners()?;
 
-    let mut workers = Vec::<Box<dyn Any>>::new();
+    let mut state = State::default();
 
     for config in config.outputs {
-        let (sink, worker) = Sink::with_config(config)?;
-        info!("Created sink: {}", sink.name);
-
-        sinks.insert(sink.name.clone(), sink);
-        workers.push(worker);
+        routing::add_sink(&mut state, config)?;
     }
 
     for config in config.sources {
-        let mut ports = Vec::new();
+        routing::add_source(&mut state, config)?;
+    }
 
-        for (_, sink) in &mut sinks {
-            let port = sink.add_source(config.name.clone());
-            ports.push(port);
+    if let Some(path) = &config.state {
+        match Snapshot::load(path) {
+            Ok(snapshot) => snapshot.restore(&mut state),
+            Err(err) => warn!("{:#} - starting from configured defaults", err),
         }
+    }
 
-        let broadcaster = Broadcaster { ports };
-
-        let (source, worker) = Source::with_config(config, broadcaster)?;
-        info!("Created source: {}", source.name);
+    let state = Arc::new(Mutex::new(state));
 
-        sources.insert(source.name.clone(), source);
-        workers.push(worker);
+    if let Some(path) = config.state.clone() {
+        tokio::spawn(persist::run(state.clone(), path));
     }
 
+    tokio::spawn(sink::report(state.clone()));
+
     info!("Initialisation completed");
 
-    proto::serve(State { sinks, sources }).await?;
+    let listeners = if activated.is_empty() {
+        let mut listeners = Vec::new();
+        for listen in config.control.listen.iter() {
+            listeners.push(Listener::bind(listen).await?);
+        }
+        listeners
+    } else {
+        info!("Using control sockets passed by systemd");
+        activated
+    };
+
+    let token = CancellationToken::new();
+    tokio::spawn(signals(token.clone(), state.clone(), opt.config.clone()));
+
+    systemd::ready(&status(&*state.lock().await));
 
-    // TODO: Really join threads here
-    for worker in workers {
-        drop(worker);
+    tokio::spawn(systemd::watchdog(state.clone()));
+
+    let result = proto::serve(listeners, state.clone(), token).await;
+    if let Err(err) = &result {
+        error!("Control server failed: {:#}", err);
     }
 
-    return Ok(());
-}
+    systemd::stopping();
 
-pub struct Broadcaster {
-    ports: Vec<Port<Sender>>,
+    let mut state = state.lock().await;
+
+    // Catch up with changes still waiting to be written
+    if let Some(path) = &config.state {
+        if let Err(err) = Snapshot::capture(&state).save(path) {
+            error!("{:#}", err);
+        }
+    }
+
+    tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown(&mut state))
+        .await
+        .context("Timeout while shutting down")?;
+
+    info!("Shutdown completed");
+
+    return result;
 }
 
-impl SourceCallback for Broadcaster {
-    fn data(&mut self, data: &[i16]) {
-        for port in self.ports.iter() {
-            if let Some(port) = &mut *port.access() {
-                port.send(data);
+/// Reloads the config on hangup and cancels the token on the first termination signal.
+async fn signals(
+    token: CancellationToken,
+    state: Arc<Mutex<State>>,
+    config: PathBuf,
+) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM"),
+            _ = interrupt.recv() => info!("Received SIGINT"),
+            _ = hangup.recv() => {
+                info!("Received SIGHUP");
+                reload(&state, &config).await;
+                continue;
             }
         }
+
+        token.cancel();
+
+        return Ok(());
+    }
+}
+
+/// Reloads the config file and applies the changes to the running state.
+async fn reload(state: &Mutex<State>, path: &Path) {
+    systemd::reloading();
+
+    let mut state = state.lock().await;
+
+    match Config::load(path) {
+        Ok(config) => routing::reload(&mut state, config).await,
+        Err(err) => error!("Failed to reload config: {:#}", err),
     }
 
-    fn idle(&mut self) {}
+    systemd::ready(&status(&state));
+}
+
+fn status(state: &State) -> String {
+    return format!(
+        "Running {} sources and {} outputs",
+        state.sources.len(),
+        state.sinks.len()
+    );
+}
+
+/// Fades out all sinks before stopping sources and sinks.
+async fn shutdown(state: &mut State) {
+    info!("Shutting down");
+
+    join_all(
+        state
+            .sinks
+            .values()
+            .map(|sink| sink.fade_out(FADE_DURATION)),
+    )
+    .await;
+
+    join_all(state.sources.values_mut().map(|source| source.stop())).await;
+    join_all(state.sinks.values_mut().map(|sink| sink.stop())).await;
 }
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info};
+
+use crate::events::Event;
+use crate::proto::State;
+
+/// Delay for collecting further changes before the state is written
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Runtime state of an output which survives restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Output {
+    volume: u8,
+    muted: bool,
+    source: Option<String>,
+}
+
+/// Runtime state of all outputs, keyed by name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    outputs: BTreeMap<String, Output>,
+}
+
+impl Snapshot {
+    pub fn capture(state: &State) -> Self {
+        let outputs = state
+            .sinks
+            .iter()
+            .map(|(name, sink)| {
+                let output = Output {
+                    volume: sink.volume(),
+                    muted: sink.muted(),
+                    source: sink
+                        .get_active_source()
+                        .map(|(source, _)| source.to_string()),
+                };
+
+                (name.to_string(), output)
+            })
+            .collect();
+
+        return Self { outputs };
+    }
+
+    /// Loads the state file, starting with an empty state if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read state file: {}", path.display()))
+            }
+        };
+
+        return serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse state file: {}", path.display()));
+    }
+
+    /// Writes the state file atomically by replacing it with a completely written copy.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut f = std::fs::File::create(&temp)
+            .with_context(|| format!("Failed to create state file: {}", temp.display()))?;
+        serde_json::to_writer_pretty(&mut f, self)?;
+        f.write_all(b"\n")?;
+        f.sync_all()?;
+
+        std::fs::rename(&temp, path)
+            .with_context(|| format!("Failed to replace state file: {}", path.display()))?;
+
+        return Ok(());
+    }
+
+    /// Applies the state to the outputs with matching names.
+    ///
+    /// Entries of outputs or sources which do not exist anymore are skipped and will vanish
+    /// with the next write.
+    pub fn restore(&self, state: &mut State) {
+        for (name, output) in self.outputs.iter() {
+            let Some(sink) = state.sinks.get_mut(&Arc::new(name.clone())) else {
+                info!("Skipping state of unknown output: {}", name);
+                continue;
+            };
+
+            sink.set_volume(output.volume);
+            sink.set_muted(output.muted);
+
+            if let Some(source) = &output.source {
+                match sink.get_source(&Arc::new(source.clone())) {
+                    Some(control) => control.switch(),
+                    None => info!("Skipping unknown source {} of output {}", source, name),
+                }
+            }
+        }
+    }
+}
+
+/// Writes the state file whenever the state has been changed.
+pub async fn run(state: Arc<Mutex<State>>, path: PathBuf) {
+    let mut events = state.lock().await.events.subscribe();
+
+    let mut last = Snapshot::capture(&*state.lock().await);
+
+    loop {
+        match events.recv().await {
+            Ok(Event::Activity { .. }) => continue,
+            Ok(_) => {}
+
+            // Missed events are caught up with by comparing the whole state
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+
+        // Volume changes tend to come in bursts
+        tokio::time::sleep(DEBOUNCE).await;
+
+        // The snapshot covers all events of the burst
+        while !matches!(
+            events.try_recv(),
+            Err(TryRecvError::Empty | TryRecvError::Closed)
+        ) {}
+
+        let snapshot = Snapshot::capture(&*state.lock().await);
+        if snapshot == last {
+            continue;
+        }
+
+        debug!("Writing state file: {}", path.display());
+        if let Err(err) = snapshot.save(&path) {
+            error!("{:#}", err);
+            continue;
+        }
+
+        last = snapshot;
+    }
+}
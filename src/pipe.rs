@@ -1,41 +1,191 @@
-use anyhow::Result;
-use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
-use std::fs::File;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, NativeEndian};
+use futures::future::BoxFuture;
+use nix::errno::Errno;
+use nix::sys::stat::{FchmodatFlags, Mode};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::pipe;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 use crate::config;
-use crate::sink::{SinkStream, SinkType};
+use crate::config::FileMode;
+use crate::sink::{SharedReceiver, SinkStream, SinkType};
 use crate::source::{SourceCallback, SourceStream, SourceType};
+use crate::supervisor::Reporter;
+use tracing::{debug, error};
 
-pub struct Pipe;
+/// Amount of audio written to a pipe sink at once
+const PERIOD: Duration = Duration::from_millis(20);
+
+/// Maximum time a pipe sink may fall behind before giving up to catch up
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+/// Pipe of a single sink or source, shared by all of its stream instances.
+#[derive(Default)]
+pub struct Pipe {
+    /// Set if the FIFO has been created by any of the stream instances, which may have failed
+    /// and been restarted since
+    created: AtomicBool,
+}
 
 pub struct PipeSourceStream {
-    running: Arc<AtomicBool>,
-    thread: Option<JoinHandle<Result<()>>>,
+    token: CancellationToken,
+    task: JoinHandle<()>,
 }
 
 pub struct PipeSinkStream {
-    running: Arc<AtomicBool>,
-    thread: Option<JoinHandle<Result<()>>>,
+    token: CancellationToken,
+    task: JoinHandle<()>,
 }
 
-impl SourceStream for PipeSourceStream {}
+impl SourceStream for PipeSourceStream {
+    fn stop(mut self: Box<Self>) -> BoxFuture<'static, ()> {
+        return Box::pin(async move {
+            self.token.cancel();
+            let _ = (&mut self.task).await;
+        });
+    }
+}
 
 impl Drop for PipeSourceStream {
     fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
-        self.thread.take().unwrap().join().unwrap().unwrap(); // TODO: Error handling
+        self.token.cancel();
     }
 }
 
-impl SinkStream for PipeSinkStream {}
+impl SinkStream for PipeSinkStream {
+    fn stop(mut self: Box<Self>) -> BoxFuture<'static, ()> {
+        return Box::pin(async move {
+            self.token.cancel();
+            let _ = (&mut self.task).await;
+        });
+    }
+}
 
 impl Drop for PipeSinkStream {
     fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
-        self.thread.take().unwrap().join().unwrap().unwrap(); // TODO: Error handling
+        self.token.cancel();
+    }
+}
+
+/// Spawns a pipe worker which reports its failure or removes the FIFO after it has been stopped.
+fn spawn(
+    remove: Option<PathBuf>,
+    reporter: Reporter,
+    worker: impl std::future::Future<Output = Result<()>> + Send + 'static,
+) -> JoinHandle<()> {
+    return tokio::spawn(async move {
+        match worker.await {
+            Ok(()) => cleanup(remove),
+            Err(err) => reporter.fail(err),
+        }
+    });
+}
+
+/// Makes sure the FIFO exists and has the configured permissions.
+///
+/// Returns the path of the FIFO if it has been created and should be removed on shutdown.
+fn prepare(config: &config::Fifo, created: &AtomicBool) -> Result<Option<PathBuf>> {
+    let created_now = match std::fs::metadata(&config.path) {
+        Ok(metadata) => {
+            if !metadata.file_type().is_fifo() {
+                bail!("Not a FIFO: {}", config.path.display());
+            }
+
+            // Reuse the FIFO left over from a previous run
+            false
+        }
+
+        Err(err) if err.kind() == ErrorKind::NotFound && config.create => {
+            if let Some(path) = config.path.parent() {
+                std::fs::create_dir_all(path)?;
+            }
+
+            nix::unistd::mkfifo(&config.path, Mode::from_bits_truncate(config.mode.0))
+                .with_context(|| format!("Failed to create FIFO: {}", config.path.display()))?;
+
+            true
+        }
+
+        Err(err) => {
+            return Err(err).with_context(|| format!("No FIFO: {}", config.path.display()));
+        }
+    };
+
+    if config.create {
+        // The mode passed to mkfifo is subject to the umask
+        set_permissions(
+            &config.path,
+            config.mode,
+            config.owner.as_deref(),
+            config.group.as_deref(),
+        )?;
+    }
+
+    // A restarted stream finds the FIFO created by its predecessor
+    let created = created.fetch_or(created_now, Ordering::Relaxed) || created_now;
+
+    return Ok((created && config.remove).then(|| config.path.clone()));
+}
+
+/// Applies the mode and changes the owner and group of a file, if given.
+pub fn set_permissions(
+    path: &Path,
+    mode: FileMode,
+    owner: Option<&str>,
+    group: Option<&str>,
+) -> Result<()> {
+    nix::sys::stat::fchmodat(
+        None,
+        path,
+        Mode::from_bits_truncate(mode.0),
+        FchmodatFlags::FollowSymlink,
+    )
+    .with_context(|| format!("Failed to change mode of {}", path.display()))?;
+
+    let owner = owner.map(resolve_user).transpose()?;
+    let group = group.map(resolve_group).transpose()?;
+    if owner.is_some() || group.is_some() {
+        nix::unistd::chown(path, owner, group)
+            .with_context(|| format!("Failed to change owner of {}", path.display()))?;
+    }
+
+    return Ok(());
+}
+
+fn resolve_user(name: &str) -> Result<Uid> {
+    if let Ok(id) = name.parse() {
+        return Ok(Uid::from_raw(id));
+    }
+
+    return Ok(User::from_name(name)?
+        .with_context(|| format!("Unknown user: {}", name))?
+        .uid);
+}
+
+fn resolve_group(name: &str) -> Result<Gid> {
+    if let Ok(id) = name.parse() {
+        return Ok(Gid::from_raw(id));
+    }
+
+    return Ok(Group::from_name(name)?
+        .with_context(|| format!("Unknown group: {}", name))?
+        .gid);
+}
+
+fn cleanup(path: Option<PathBuf>) {
+    if let Some(path) = path {
+        if let Err(err) = std::fs::remove_file(&path) {
+            error!("Failed to remove FIFO {}: {}", path.display(), err);
+        }
     }
 }
 
@@ -44,47 +194,73 @@ impl SourceType for Pipe {
     type Stream = PipeSourceStream;
 
     fn source(
+        &self,
         _name: &str,
-        config: Self::Config,
+        config: &Self::Config,
         callback: impl SourceCallback + 'static,
+        reporter: Reporter,
     ) -> Result<Self::Stream> {
-        if let Some(path) = config.path.parent() {
-            std::fs::create_dir_all(path)?;
-        }
-
-        if config.create {
-            nix::unistd::mkfifo(&config.path, nix::sys::stat::Mode::all())?;
-        }
+        let remove = prepare(&config.fifo, &self.created)?;
 
-        let running = Arc::new(AtomicBool::new(true));
+        let token = CancellationToken::new();
 
-        let f = std::fs::OpenOptions::new().read(true).open(&config.path)?;
+        let task = spawn(
+            remove,
+            reporter,
+            source_worker(
+                callback,
+                config.fifo.path.clone(),
+                config.channels,
+                token.clone(),
+            ),
+        );
 
-        let thread = std::thread::spawn(source_worker(callback, f, running.clone()));
-
-        return Ok(Self::Stream {
-            running,
-            thread: Some(thread),
-        });
+        return Ok(Self::Stream { token, task });
     }
 }
 
-fn source_worker(
+async fn source_worker(
     mut callback: impl SourceCallback,
-    mut f: File,
-    running: Arc<AtomicBool>,
-) -> impl FnOnce() -> Result<()> {
-    return move || {
-        let mut data = [0i16; 64];
+    path: PathBuf,
+    channels: u16,
+    token: CancellationToken,
+) -> Result<()> {
+    let frame = channels as usize * 2;
 
-        while running.load(Ordering::Relaxed) {
-            f.read_i16_into::<NativeEndian>(&mut data)?;
+    let mut buffer = vec![0u8; frame * 1024];
+    let mut data = vec![0i16; buffer.len() / 2];
 
-            callback.data(&data);
-        }
+    loop {
+        // Opening for reading succeeds immediately, even without a writer
+        let mut pipe = pipe::OpenOptions::new().open_receiver(&path)?;
 
-        return Ok(());
-    };
+        // Bytes of an incomplete frame carried over from the previous read
+        let mut pending = 0;
+
+        loop {
+            let n = tokio::select! {
+                _ = token.cancelled() => return Ok(()),
+                n = pipe.read(&mut buffer[pending..]) => n?,
+            };
+
+            // The writer has closed the pipe - wait for the next one
+            if n == 0 {
+                debug!("Pipe writer disconnected: {}", path.display());
+                callback.idle();
+                break;
+            }
+
+            let available = pending + n;
+            let complete = available - available % frame;
+
+            let samples = &mut data[..complete / 2];
+            NativeEndian::read_i16_into(&buffer[..complete], samples);
+            callback.data(samples);
+
+            buffer.copy_within(complete..available, 0);
+            pending = available - complete;
+        }
+    }
 }
 
 impl SinkType for Pipe {
@@ -92,47 +268,109 @@ impl SinkType for Pipe {
     type Stream = PipeSinkStream;
 
     fn sink(
+        &self,
         _name: &str,
-        config: Self::Config,
-        rx: ringbuf::HeapConsumer<i16>,
+        config: &Self::Config,
+        rx: SharedReceiver,
+        reporter: Reporter,
     ) -> Result<Self::Stream> {
-        if let Some(path) = config.path.parent() {
-            std::fs::create_dir_all(path)?;
+        let remove = prepare(&config.fifo, &self.created)?;
+
+        let token = CancellationToken::new();
+
+        let task = spawn(
+            remove,
+            reporter,
+            sink_worker(
+                rx,
+                config.fifo.path.clone(),
+                config.rate,
+                config.channels,
+                token.clone(),
+            ),
+        );
+
+        return Ok(Self::Stream { token, task });
+    }
+}
+
+async fn sink_worker(
+    rx: SharedReceiver,
+    path: PathBuf,
+    rate: u32,
+    channels: u16,
+    token: CancellationToken,
+) -> Result<()> {
+    // Wait for a previous instance of the stream to release the receiver
+    let mut rx = tokio::select! {
+        _ = token.cancelled() => return Ok(()),
+        rx = rx.lock_owned() => rx,
+    };
+
+    rx.set_format(rate, channels);
+    let frames = (rate as u64 * PERIOD.as_millis() as u64 / 1000) as usize;
+
+    let mut data = vec![0i16; frames * channels as usize];
+    let mut buffer = vec![0u8; data.len() * 2];
+
+    let mut pipe = None;
+
+    let mut next = Instant::now();
+
+    loop {
+        // Pace output at the sample rate instead of writing as fast as data arrives
+        if Instant::now() - next > MAX_LAG {
+            next = Instant::now();
         }
 
-        if config.create {
-            nix::unistd::mkfifo(&config.path, nix::sys::stat::Mode::all())?;
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep_until(next) => {},
         }
 
-        let running = Arc::new(AtomicBool::new(true));
+        next += PERIOD;
 
-        let f = std::fs::OpenOptions::new().write(true).open(&config.path)?;
+        // Wait up to one period for the sources to deliver
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = rx.fill_until(&mut data, next) => {},
+        }
 
-        let thread = std::thread::spawn(sink_worker(rx, f, running.clone()));
+        // Data is discarded while there is no reader to keep the buffer from overflowing
+        if pipe.is_none() {
+            pipe = open_sender(&path)?;
+        }
 
-        return Ok(Self::Stream {
-            running,
-            thread: Some(thread),
-        });
-    }
-}
+        let Some(sender) = &mut pipe else {
+            continue;
+        };
 
-fn sink_worker(
-    mut rx: ringbuf::HeapConsumer<i16>,
-    mut f: File,
-    running: Arc<AtomicBool>,
-) -> impl FnOnce() -> Result<()> {
-    return move || {
-        let mut data = [0i16; 64];
+        NativeEndian::write_i16_into(&data, &mut buffer);
 
-        while running.load(Ordering::Relaxed) {
-            let i = rx.pop_slice(&mut data);
+        let result = tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            result = sender.write_all(&buffer) => result,
+        };
 
-            for d in &data[0..i] {
-                f.write_i16::<NativeEndian>(*d)?;
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                debug!("Pipe reader disconnected: {}", path.display());
+                pipe = None;
             }
+            Err(err) => return Err(err.into()),
         }
+    }
+}
 
-        return Ok(());
+/// Opens the pipe for writing, returns `None` if there is no reader yet.
+fn open_sender(path: &Path) -> Result<Option<pipe::Sender>> {
+    return match pipe::OpenOptions::new().open_sender(path) {
+        Ok(sender) => {
+            debug!("Pipe reader connected: {}", path.display());
+            Ok(Some(sender))
+        }
+        Err(err) if err.raw_os_error() == Some(Errno::ENXIO as i32) => Ok(None),
+        Err(err) => Err(err.into()),
     };
 }
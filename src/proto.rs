@@ -1,58 +1,248 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::DirBuilder;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::io::ErrorKind;
 use std::ops::Deref;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use futures::future::try_join_all;
 use futures::SinkExt;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{mpsc, Mutex};
 use tokio_stream::StreamExt;
 use tokio_util::codec::{Framed, LinesCodec};
-use tracing::{debug, error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
+use crate::config;
 use crate::config::Named;
+use crate::events::Events;
+use crate::pipe;
+use crate::routing::Broadcaster;
 use crate::sink::Sink;
 use crate::source::Source;
 
+#[derive(Default)]
 pub struct State {
     pub sources: HashMap<Arc<String>, Named<Source>>,
     pub sinks: HashMap<Arc<String>, Named<Sink>>,
+
+    /// Connections of each source to the sinks
+    pub broadcasters: HashMap<Arc<String>, Broadcaster>,
+
+    /// Changes of volume, mute state and active source of sinks and of source activity
+    pub events: Events,
+}
+
+const JSONRPC_TAG: &str = "2.0";
+
+/// Interval at which the state is checked for changes not announced as events, like failing
+/// streams and reloads
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A bidirectional stream of a control connection.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub enum Listener {
+    Tcp(TcpListener),
+
+    /// Unix domain socket, with the path to remove when the listener stops
+    Unix(UnixListener, Option<PathBuf>),
 }
 
-const JSONRPC_TAG: &'static str = "2.0";
+impl Listener {
+    pub async fn bind(listen: &config::Listen) -> Result<Self> {
+        return match listen {
+            config::Listen::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind control socket: {}", addr))?;
+
+                Ok(Self::Tcp(listener))
+            }
+
+            config::Listen::Unix(config) => {
+                // Replace a socket left over from a previous run, but not one still in use
+                if std::fs::symlink_metadata(&config.path)
+                    .is_ok_and(|metadata| metadata.file_type().is_socket())
+                {
+                    match std::os::unix::net::UnixStream::connect(&config.path) {
+                        Ok(_) => bail!("Control socket already in use: {}", config.path.display()),
+                        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                            std::fs::remove_file(&config.path)?;
+                        }
+                        Err(err) => {
+                            return Err(err).with_context(|| {
+                                format!("Failed to check control socket: {}", config.path.display())
+                            });
+                        }
+                    }
+                }
 
-pub async fn serve(state: State) -> Result<()> {
-    let listener = TcpListener::bind("[::]:1705").await?;
+                let listener = bind_private(config).with_context(|| {
+                    format!("Failed to bind control socket: {}", config.path.display())
+                })?;
 
-    let shared = Arc::new(Mutex::new(Shared {
-        clients: HashMap::new(),
-        state: Arc::new(Mutex::new(state)),
-    }));
+                Ok(Self::Unix(listener, Some(config.path.clone())))
+            }
+        };
+    }
+
+    /// Accepts a connection and returns it with a description of the peer.
+    async fn accept(&self) -> std::io::Result<(Box<dyn Connection>, String)> {
+        return match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), addr.to_string()))
+            }
+
+            Self::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+
+                let peer = match stream.peer_cred() {
+                    Ok(cred) => format!("unix socket (uid {})", cred.uid()),
+                    Err(_) => "unix socket".to_string(),
+                };
+
+                Ok((Box::new(stream), peer))
+            }
+        };
+    }
+}
+
+/// Binds a unix socket in a private directory and moves it into place once its permissions are
+/// applied, so it is never reachable with the permissions given by the umask.
+fn bind_private(config: &config::UnixSocket) -> Result<UnixListener> {
+    let parent = config
+        .path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut name = OsString::from(".");
+    name.push(config.path.file_name().unwrap_or_default());
+    name.push(format!(".{}", std::process::id()));
+
+    let private = parent.join(name);
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed to create directory: {}", private.display()))?;
+
+    let temp = private.join("socket");
+    let result = (|| {
+        let listener = UnixListener::bind(&temp)?;
+
+        pipe::set_permissions(
+            &temp,
+            config.mode,
+            config.owner.as_deref(),
+            config.group.as_deref(),
+        )?;
+
+        std::fs::rename(&temp, &config.path)?;
+
+        return Ok(listener);
+    })();
+
+    if let Err(err) = std::fs::remove_dir_all(&private) {
+        error!("Failed to remove directory {}: {}", private.display(), err);
+    }
 
+    return result;
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, Some(path)) = self {
+            if let Err(err) = std::fs::remove_file(&path) {
+                error!(
+                    "Failed to remove control socket {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Serves control connections on all listeners until the token is cancelled.
+///
+/// Cancelling the token stops accepting new connections and closes all open ones.
+pub async fn serve(
+    listeners: Vec<Listener>,
+    state: Arc<Mutex<State>>,
+    token: CancellationToken,
+) -> Result<()> {
+    let shared = Arc::new(Mutex::new(Shared::new(state).await));
+
+    tokio::spawn(watch(shared.clone(), token.clone()));
+
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| accept(listener, shared.clone(), token.clone())),
+    )
+    .await?;
+
+    return Ok(());
+}
+
+async fn accept(
+    listener: Listener,
+    shared: Arc<Mutex<Shared>>,
+    token: CancellationToken,
+) -> Result<()> {
     loop {
-        let (stream, addr) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            _ = token.cancelled() => break,
+            result = listener.accept() => result?,
+        };
 
         let shared = shared.clone();
+        let token = token.clone();
 
         tokio::spawn(async move {
-            debug!("Accepted connection: {}", addr);
-            if let Err(e) = process(shared, stream, addr).await {
+            debug!("Accepted connection: {}", peer);
+            if let Err(e) = process(shared, stream, token).await {
                 info!("Error occurred: {}", e);
             }
         });
     }
+
+    return Ok(());
 }
 
-async fn process(shared: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+/// Handles requests of a single client until it disconnects.
+pub(crate) async fn process(
+    shared: Arc<Mutex<Shared>>,
+    stream: Box<dyn Connection>,
+    token: CancellationToken,
+) -> Result<()> {
     let (tx, mut rx) = mpsc::channel(16);
 
     // Register this client for broadcasting
-    shared.lock().await.clients.insert(addr, tx.clone());
+    let id = {
+        let mut shared = shared.lock().await;
+
+        let id = shared.next_client;
+        shared.next_client += 1;
+
+        shared.clients.insert(id, tx);
+        id
+    };
 
     // Framer codec for line based protocol
     let mut lines = Framed::new(stream, LinesCodec::new());
@@ -60,21 +250,11 @@ async fn process(shared: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr
     // Process incoming messages until disconnected
     loop {
         tokio::select! {
-            Some(res) = rx.recv() => {
-                // Encode response to JSON
-                let res = match serde_json::to_string(&res) {
-                    Ok(res) => res,
-                    Err(err) => {
-                        error!("Protocol error: {}", err);
-                        break;
-                    }
-                };
+            _ = token.cancelled() => break,
 
-                debug!("Response to send: {:?}", res);
-
-                // Send response line to client
-                if let Err(err) = lines.send(&res).await {
-                    error!("TCP error: {}", err);
+            Some(notification) = rx.recv() => {
+                if let Err(err) = send(&mut lines, &notification).await {
+                    error!("Connection error: {:#}", err);
                     break;
                 }
             }
@@ -88,31 +268,42 @@ async fn process(shared: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr
 
                     debug!("Parse request: {}", req);
 
-                    let mut shared = shared.lock().await;
-
-                    let res = match serde_json::from_str::<Request>(&req) {
-                        Ok(req) => match shared.dispatch(&req).await {
-                            Ok(res) => match serde_json::to_value(res) {
-                                Ok(res) => req.id.and_then(|id| Some(Response::ok(res).with_id(Some(id)))),
-                                Err(err) => {
-                                    error!("Protocol error: {}", err);
-                                    break;
+                    let res = {
+                        let mut shared = shared.lock().await;
+
+                        let res = match serde_json::from_str::<Request>(req) {
+                            Ok(req) => match shared.dispatch(&req).await {
+                                Ok(res) => match serde_json::to_value(res) {
+                                    Ok(res) => req.id.map(|id| Response::ok(res).with_id(Some(id))),
+                                    Err(err) => {
+                                        error!("Protocol error: {}", err);
+                                        break;
+                                    }
                                 }
+                                Err(err) => Some(Response::error(err).with_id(req.id)),
                             }
-                            Err(err) => Some(Response::error(err).with_id(req.id)),
-                        }
-                        Err(err) => Some(Response::error(ResponseError::parse_error(err)))
+                            Err(err) => Some(Response::error(ResponseError::parse_error(err)))
+                        };
+
+                        // The requesting client learns about its changes from the response
+                        shared.announce(Some(id)).await;
+
+                        res
                     };
 
+                    // Responses are sent directly, as the channel may be filled up with
+                    // notifications
                     if let Some(res) = res {
                         debug!("Dispatch response: {:?}", res);
-                        tx.send(res).await
-                                .expect("Send response");
+                        if let Err(err) = send(&mut lines, &res).await {
+                            error!("Connection error: {:#}", err);
+                            break;
+                        }
                     }
                 }
 
                 Some(Err(err)) => {
-                    error!("TCP error: {}", err);
+                    error!("Connection error: {}", err);
                     break;
                 }
 
@@ -123,29 +314,237 @@ async fn process(shared: Arc<Mutex<Shared>>, stream: TcpStream, addr: SocketAddr
     }
 
     // Client has disconnected - deregister
-    shared.lock().await.clients.remove(&addr);
+    shared.lock().await.clients.remove(&id);
+
+    return Ok(());
+}
+
+async fn send(
+    lines: &mut Framed<Box<dyn Connection>, LinesCodec>,
+    message: &impl Serialize,
+) -> Result<()> {
+    let message = serde_json::to_string(message)?;
+
+    debug!("Message to send: {:?}", message);
+
+    lines.send(&message).await?;
 
     return Ok(());
 }
 
-#[derive(Deserialize, Debug)]
-struct Request {
+/// Announces changes not caused by requests, like sources becoming idle or failing streams.
+async fn watch(shared: Arc<Mutex<Shared>>, token: CancellationToken) {
+    let mut events = shared.lock().await.state.lock().await.events.subscribe();
+
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = interval.tick() => {}
+
+            // Missed events are caught up with by comparing the whole state
+            event = events.recv() => if let Err(RecvError::Closed) = event {
+                break;
+            }
+        }
+
+        shared.lock().await.announce(None).await;
+    }
+}
+
+/// Observable state of a sink.
+#[derive(Debug, Clone, PartialEq)]
+struct SinkView {
+    volume: u8,
+    muted: bool,
+    source: Option<Arc<String>>,
+    healthy: bool,
+}
+
+/// Observable state of a source.
+#[derive(Debug, Clone, PartialEq)]
+struct SourceView {
+    active: bool,
+    healthy: bool,
+}
+
+/// Observable state of all sinks and sources, used to find changes to notify clients about.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct View {
+    sinks: HashMap<Arc<String>, SinkView>,
+    sources: HashMap<Arc<String>, SourceView>,
+}
+
+impl View {
+    fn capture(state: &State) -> Self {
+        let sinks = state
+            .sinks
+            .iter()
+            .map(|(name, sink)| {
+                let view = SinkView {
+                    volume: sink.volume(),
+                    muted: sink.muted(),
+                    source: sink.get_active_source().map(|(source, _)| source),
+                    healthy: sink.health().is_healthy(),
+                };
+
+                (name.clone(), view)
+            })
+            .collect();
+
+        let sources = state
+            .sources
+            .iter()
+            .map(|(name, source)| {
+                let view = SourceView {
+                    active: source.is_active(),
+                    healthy: source.health().is_healthy(),
+                };
+
+                (name.clone(), view)
+            })
+            .collect();
+
+        return Self { sinks, sources };
+    }
+
+    /// Builds the notifications about the changes from this view to the current one.
+    fn changes(&self, current: &Self, state: &State) -> Vec<Request> {
+        fn notification(method: &str, params: impl Serialize) -> Request {
+            return Request::new(None, method, params).expect("Serialize notification");
+        }
+
+        // Added and removed streams as well as lost or recovered clients change the whole server
+        let restructured = self.sinks.len() != current.sinks.len()
+            || self.sources.len() != current.sources.len()
+            || current.sinks.iter().any(|(name, sink)| {
+                !self
+                    .sinks
+                    .get(name)
+                    .is_some_and(|last| last.healthy == sink.healthy)
+            })
+            || current
+                .sources
+                .keys()
+                .any(|name| !self.sources.contains_key(name));
+
+        if restructured {
+            let server = types::ServerUpdate {
+                server: types::Server::from(state),
+            };
+
+            return vec![notification("Server.OnUpdate", server)];
+        }
+
+        let mut notifications = Vec::new();
+
+        for (name, sink) in current.sinks.iter() {
+            let (Some(last), Some(state)) = (self.sinks.get(name), state.sinks.get(name)) else {
+                continue;
+            };
+
+            let id = name.to_string();
+
+            if last.volume != sink.volume || last.muted != sink.muted {
+                let volume = types::ClientVolume {
+                    volume: types::Volume::from(state),
+                };
+
+                notifications.push(notification(
+                    "Client.OnVolumeChanged",
+                    WithId {
+                        id: id.clone(),
+                        inner: volume,
+                    },
+                ));
+            }
+
+            if last.muted != sink.muted {
+                let mute = types::Mute { mute: sink.muted };
+
+                notifications.push(notification(
+                    "Group.OnMute",
+                    WithId {
+                        id: id.clone(),
+                        inner: mute,
+                    },
+                ));
+            }
+
+            if last.source != sink.source {
+                let stream = types::StreamId {
+                    stream_id: sink.source.clone().unwrap_or_default(),
+                };
+
+                notifications.push(notification(
+                    "Group.OnStreamChanged",
+                    WithId { id, inner: stream },
+                ));
+            }
+        }
+
+        for (name, source) in current.sources.iter() {
+            let (Some(last), Some(state)) = (self.sources.get(name), state.sources.get(name))
+            else {
+                continue;
+            };
+
+            if last != source {
+                let stream = types::StreamUpdate {
+                    stream: types::Stream::from(state),
+                };
+
+                notifications.push(notification(
+                    "Stream.OnUpdate",
+                    WithId {
+                        id: name.to_string(),
+                        inner: stream,
+                    },
+                ));
+            }
+        }
+
+        return notifications;
+    }
+}
+
+/// A request, or a notification if it has no ID.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Request {
     #[serde(rename = "jsonrpc")]
-    #[allow(unused)]
-    tag: String,
+    pub tag: String,
 
-    id: Option<String>,
+    pub id: Option<String>,
 
-    method: String,
+    pub method: String,
 
     #[serde(default)]
-    params: Map<String, Value>,
+    pub params: Map<String, Value>,
+}
+
+impl Request {
+    pub fn new(id: Option<String>, method: &str, params: impl Serialize) -> Result<Self> {
+        let params = match serde_json::to_value(params)? {
+            Value::Object(params) => params,
+            Value::Null => Map::new(),
+            params => bail!("Parameters must be an object: {}", params),
+        };
+
+        return Ok(Self {
+            tag: JSONRPC_TAG.to_string(),
+            id,
+            method: method.to_string(),
+            params,
+        });
+    }
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
-struct ResponseError {
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ResponseError {
     pub code: i32,
     pub message: String,
+
+    #[serde(default)]
     pub data: Value,
 }
 
@@ -186,18 +585,18 @@ impl ResponseError {
     }
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
-enum ResponseData {
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum ResponseData {
     #[serde(rename = "result")]
     Result(Value),
     #[serde(rename = "error")]
     Error(ResponseError),
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
-struct Response {
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response {
     #[serde(rename = "jsonrpc")]
-    pub tag: &'static str,
+    pub tag: String,
 
     pub id: Option<String>,
 
@@ -208,7 +607,7 @@ struct Response {
 impl Response {
     pub fn ok(value: Value) -> Self {
         return Self {
-            tag: JSONRPC_TAG,
+            tag: JSONRPC_TAG.to_string(),
             id: None,
             data: ResponseData::Result(value),
         };
@@ -216,7 +615,7 @@ impl Response {
 
     pub fn error(error: ResponseError) -> Self {
         return Self {
-            tag: JSONRPC_TAG,
+            tag: JSONRPC_TAG.to_string(),
             id: None,
             data: ResponseData::Error(error),
         };
@@ -228,13 +627,60 @@ impl Response {
     }
 }
 
-struct Shared {
-    clients: HashMap<SocketAddr, mpsc::Sender<Response>>,
+/// State shared by all connections of the control server.
+pub(crate) struct Shared {
+    clients: HashMap<usize, mpsc::Sender<Request>>,
+    next_client: usize,
 
     state: Arc<Mutex<State>>,
+
+    /// State as last announced to the clients
+    view: View,
 }
 
 impl Shared {
+    pub(crate) async fn new(state: Arc<Mutex<State>>) -> Self {
+        let view = View::capture(&*state.lock().await);
+
+        return Self {
+            clients: HashMap::new(),
+            next_client: 0,
+            state,
+            view,
+        };
+    }
+
+    /// Notifies all clients except the given one about the changes since the last announcement.
+    async fn announce(&mut self, except: Option<usize>) {
+        let state = self.state.lock().await;
+
+        let view = View::capture(&state);
+        if view == self.view {
+            return;
+        }
+
+        let notifications = self.view.changes(&view, &state);
+        drop(state);
+
+        self.view = view;
+
+        for notification in notifications {
+            for (id, client) in self.clients.iter() {
+                if Some(*id) == except {
+                    continue;
+                }
+
+                // Never wait for a client while holding the lock
+                if client.try_send(notification.clone()).is_err() {
+                    warn!(
+                        "Dropping notification for client {}: {}",
+                        id, notification.method
+                    );
+                }
+            }
+        }
+    }
+
     async fn dispatch(&mut self, req: &Request) -> Result<Value, ResponseError> {
         async fn dispatch<'a, F, P, R, A>(
             shared: &'a mut Shared,
@@ -252,17 +698,19 @@ impl Shared {
 
             let value = f(shared, params).await?;
 
-            return Ok(
-                serde_json::to_value(value).map_err(|err| ResponseError::error(-32603, err))?
-            );
+            return serde_json::to_value(value).map_err(|err| ResponseError::error(-32603, err));
         }
 
         return Ok(match req.method.as_str() {
+            "Client.GetHealth" => dispatch(self, req, Self::client_get_health).await?,
+            "Client.GetStats" => dispatch(self, req, Self::client_get_stats).await?,
             "Client.GetStatus" => dispatch(self, req, Self::client_get_status).await?,
+            "Client.SetLatency" => dispatch(self, req, Self::client_set_latency).await?,
             "Client.SetVolume" => dispatch(self, req, Self::client_set_volume).await?,
             "Group.GetStatus" => dispatch(self, req, Self::group_get_status).await?,
             "Group.SetMute" => dispatch(self, req, Self::group_set_mute).await?,
             "Group.SetStream" => dispatch(self, req, Self::group_set_stream).await?,
+            "Stream.GetHealth" => dispatch(self, req, Self::stream_get_health).await?,
             "Server.GetRPCVersion" => dispatch(self, req, Self::server_get_rpc_version).await?,
             "Server.GetStatus" => dispatch(self, req, Self::server_get_status).await?,
             _ => {
@@ -274,6 +722,32 @@ impl Shared {
         });
     }
 
+    async fn client_get_stats(
+        &mut self,
+        params: WithId<types::Empty>,
+    ) -> Result<types::Stats, ResponseError> {
+        let state = self.state.lock().await;
+
+        let sink = state.sinks.get(&params.id).ok_or_else(|| {
+            ResponseError::invalid_params(format!("Unknown client: {}", params.id))
+        })?;
+
+        return Ok(types::Stats::from(sink));
+    }
+
+    async fn client_get_health(
+        &mut self,
+        params: WithId<types::Empty>,
+    ) -> Result<types::Health, ResponseError> {
+        let state = self.state.lock().await;
+
+        let sink = state.sinks.get(&params.id).ok_or_else(|| {
+            ResponseError::invalid_params(format!("Unknown client: {}", params.id))
+        })?;
+
+        return Ok(types::Health::from(sink.health()));
+    }
+
     async fn client_get_status(
         &mut self,
         params: WithId<types::Empty>,
@@ -287,6 +761,22 @@ impl Shared {
         return Ok(types::Client::from(sink));
     }
 
+    async fn client_set_latency(
+        &mut self,
+        params: WithId<types::Latency>,
+    ) -> Result<types::Latency, ResponseError> {
+        let mut state = self.state.lock().await;
+
+        let sink = state.sinks.get_mut(&params.id).ok_or_else(|| {
+            ResponseError::invalid_params(format!("Unknown client: {}", params.id))
+        })?;
+
+        sink.set_latency(params.latency)
+            .map_err(|err| ResponseError::invalid_params(err.to_string()))?;
+
+        return Ok(params.inner);
+    }
+
     async fn client_set_volume(
         &mut self,
         params: WithId<types::Volume>,
@@ -357,6 +847,19 @@ impl Shared {
         return Ok(types::Stream::from(source));
     }
 
+    async fn stream_get_health(
+        &mut self,
+        params: WithId<types::Empty>,
+    ) -> Result<types::Health, ResponseError> {
+        let state = self.state.lock().await;
+
+        let source = state.sources.get(&params.id).ok_or_else(|| {
+            ResponseError::invalid_params(format!("Unknown stream: {}", params.id))
+        })?;
+
+        return Ok(types::Health::from(source.health()));
+    }
+
     async fn server_get_rpc_version(
         &mut self,
         _params: types::Empty,
@@ -370,32 +873,12 @@ impl Shared {
     ) -> Result<types::Server, ResponseError> {
         let state = self.state.lock().await;
 
-        // Create a group for each sink
-        let groups = state
-            .sinks
-            .values()
-            .map(types::Group::from)
-            .collect::<Vec<_>>();
-
-        let streams = state
-            .sources
-            .values()
-            .map(types::Stream::from)
-            .collect::<Vec<_>>();
-
-        return Ok(types::Server {
-            server: types::ServerInner {
-                host: types::Host::default(),
-                meta: types::Meta::default(),
-            },
-            groups,
-            streams,
-        });
+        return Ok(types::Server::from(&state));
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct WithId<I> {
+pub struct WithId<I> {
     pub id: String,
 
     #[serde(flatten)]
@@ -410,7 +893,7 @@ impl<I> Deref for WithId<I> {
     }
 }
 
-mod types {
+pub mod types {
     use std::sync::Arc;
     use std::time::SystemTime;
 
@@ -418,8 +901,10 @@ mod types {
     use url::Url;
 
     use crate::config::Named;
+    use crate::proto::State;
     use crate::sink::Sink;
     use crate::source::Source;
+    use crate::supervisor;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Empty {}
@@ -430,6 +915,25 @@ mod types {
         pub percent: f32,
     }
 
+    impl Volume {
+        pub fn from(sink: &Named<Sink>) -> Self {
+            return Self {
+                muted: sink.muted(),
+                percent: sink.volume() as f32 / u8::MAX as f32 * 100.0,
+            };
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ClientVolume {
+        pub volume: Volume,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Latency {
+        pub latency: u32,
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Host {}
 
@@ -500,23 +1004,55 @@ mod types {
         pub fn from(sink: &Named<Sink>) -> Self {
             return Self {
                 id: sink.name.clone(),
-                connected: true,
+                connected: sink.health().is_healthy(),
                 host: Host::default(),
                 last_seen: Timestamp::now(),
                 config: ClientConfig {
                     name: sink.name().to_string(),
                     instance: 0,
-                    latency: 0,
-                    volume: Volume {
-                        muted: sink.muted(),
-                        percent: sink.volume() as f32 / u8::MAX as f32 * 100.0,
-                    },
+                    latency: sink.latency(),
+                    volume: Volume::from(sink),
                 },
                 meta: Default::default(),
             };
         }
     }
 
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Stats {
+        pub dropped: u64,
+        pub padded: u64,
+        pub timeouts: u64,
+    }
+
+    impl Stats {
+        pub fn from(sink: &Named<Sink>) -> Self {
+            return Self {
+                dropped: sink.stats().dropped(),
+                padded: sink.stats().padded(),
+                timeouts: sink.stats().timeouts(),
+            };
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Health {
+        pub healthy: bool,
+        pub restarts: u32,
+        #[serde(rename = "lastError")]
+        pub last_error: Option<String>,
+    }
+
+    impl Health {
+        pub fn from(health: &supervisor::Health) -> Self {
+            return Self {
+                healthy: health.is_healthy(),
+                restarts: health.restarts(),
+                last_error: health.last_error(),
+            };
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Group {
         pub id: Arc<String>,
@@ -586,6 +1122,11 @@ mod types {
         }
     }
 
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct StreamUpdate {
+        pub stream: Stream,
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Version {
         pub major: u32,
@@ -616,4 +1157,23 @@ mod types {
         pub groups: Vec<Group>,
         pub streams: Vec<Stream>,
     }
+
+    impl Server {
+        pub fn from(state: &State) -> Self {
+            return Self {
+                server: ServerInner {
+                    host: Host::default(),
+                    meta: Meta::default(),
+                },
+                // Create a group for each sink
+                groups: state.sinks.values().map(Group::from).collect(),
+                streams: state.sources.values().map(Stream::from).collect(),
+            };
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ServerUpdate {
+        pub server: Server,
+    }
 }
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use tracing::{error, info};
+
+use crate::config;
+use crate::config::{Config, Named};
+use crate::proto::State;
+use crate::sink::{Sender, Sink};
+use crate::source::{Source, SourceCallback};
+use crate::switcher::Port;
+
+/// Distributes the data of a source to all sinks.
+///
+/// Clones share their ports, so sinks can be connected and disconnected while the source is
+/// running. Changes publish a new copy of the ports, so the source never waits for them.
+#[derive(Clone, Default)]
+pub struct Broadcaster {
+    ports: Arc<ArcSwap<HashMap<Arc<String>, Port<Sender>>>>,
+}
+
+impl Broadcaster {
+    fn insert(&self, sink: Arc<String>, port: Port<Sender>) {
+        self.ports.rcu(|ports| {
+            let mut ports = HashMap::clone(ports);
+            ports.insert(sink.clone(), port.clone());
+            return ports;
+        });
+    }
+
+    fn remove(&self, sink: &Arc<String>) {
+        self.ports.rcu(|ports| {
+            let mut ports = HashMap::clone(ports);
+            ports.remove(sink);
+            return ports;
+        });
+    }
+}
+
+impl SourceCallback for Broadcaster {
+    fn data(&mut self, data: &[i16]) {
+        for port in self.ports.load().values() {
+            if let Some(mut sender) = port.access() {
+                sender.send(data);
+            }
+        }
+    }
+
+    fn idle(&mut self) {}
+}
+
+/// Creates a sink and connects it to all sources.
+pub fn add_sink(state: &mut State, config: Named<config::Output>) -> Result<&mut Named<Sink>> {
+    let mut sink = Sink::with_config(config, state.events.clone())?;
+
+    for (source, broadcaster) in state.broadcasters.iter() {
+        let port = sink.add_source(source.clone());
+        broadcaster.insert(sink.name.clone(), port);
+    }
+
+    if let Some(source) = sink.config().source.clone() {
+        if let Some(control) = sink.get_source(&source) {
+            control.switch();
+        }
+    }
+
+    info!("Created sink: {}", sink.name);
+
+    return Ok(state.sinks.entry(sink.name.clone()).or_insert(sink));
+}
+
+/// Disconnects a sink from all sources and stops it.
+pub async fn remove_sink(state: &mut State, name: &Arc<String>) -> Option<Named<Sink>> {
+    for broadcaster in state.broadcasters.values() {
+        broadcaster.remove(name);
+    }
+
+    let mut sink = state.sinks.remove(name)?;
+    sink.stop().await;
+
+    info!("Removed sink: {}", name);
+
+    return Some(sink);
+}
+
+/// Creates a source and connects it to all sinks.
+///
+/// A source replacing a previous one with the same name takes over its connections.
+pub fn add_source(state: &mut State, config: Named<config::Source>) -> Result<()> {
+    let broadcaster = state
+        .broadcasters
+        .entry(config.name.clone())
+        .or_insert_with(|| {
+            let broadcaster = Broadcaster::default();
+
+            for sink in state.sinks.values_mut() {
+                let port = sink.add_source(config.name.clone());
+                broadcaster.insert(sink.name.clone(), port);
+
+                if sink.config().source.as_ref() == Some(&config.name)
+                    && sink.get_active_source().is_none()
+                {
+                    if let Some(control) = sink.get_source(&config.name) {
+                        control.switch();
+                    }
+                }
+            }
+
+            broadcaster
+        })
+        .clone();
+
+    let source = Source::with_config(config, broadcaster, state.events.clone())?;
+    info!("Created source: {}", source.name);
+
+    state.sources.insert(source.name.clone(), source);
+
+    return Ok(());
+}
+
+/// Stops a source and disconnects it from all sinks.
+pub async fn remove_source(state: &mut State, name: &Arc<String>) {
+    if let Some(mut source) = state.sources.remove(name) {
+        source.stop().await;
+    }
+
+    state.broadcasters.remove(name);
+
+    for sink in state.sinks.values_mut() {
+        sink.remove_source(name);
+    }
+
+    info!("Removed source: {}", name);
+}
+
+/// Applies a changed configuration to the running state.
+///
+/// Only added, removed or changed sources and sinks are recreated. Recreated sinks keep their
+/// volume, mute state and active source.
+pub async fn reload(state: &mut State, config: Config) {
+    let outputs = config
+        .outputs
+        .into_iter()
+        .map(|output| (output.name.clone(), output))
+        .collect::<HashMap<_, _>>();
+
+    let sources = config
+        .sources
+        .into_iter()
+        .map(|source| (source.name.clone(), source))
+        .collect::<HashMap<_, _>>();
+
+    // Sources known before, including the ones which failed to start
+    let removed = state
+        .broadcasters
+        .keys()
+        .filter(|name| !sources.contains_key(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in removed {
+        remove_source(state, &name).await;
+    }
+
+    let changed = state
+        .sinks
+        .iter()
+        .filter(|(name, sink)| {
+            !outputs
+                .get(*name)
+                .is_some_and(|output| output.same_output(sink.config()))
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    let mut previous = HashMap::new();
+    for name in changed {
+        if let Some(sink) = remove_sink(state, &name).await {
+            previous.insert(name, sink);
+        }
+    }
+
+    for (name, output) in outputs {
+        if state.sinks.contains_key(&name) {
+            continue;
+        }
+
+        let sink = match add_sink(state, output) {
+            Ok(sink) => sink,
+            Err(err) => {
+                error!("Failed to create sink {}: {:#}", name, err);
+                continue;
+            }
+        };
+
+        if let Some(previous) = previous.get(&name) {
+            sink.set_volume(previous.volume());
+            sink.set_muted(previous.muted());
+
+            if let Some((source, _)) = previous.get_active_source() {
+                if let Some(control) = sink.get_source(&source) {
+                    control.switch();
+                }
+            }
+        }
+    }
+
+    for (name, source) in sources {
+        match state.sources.get_mut(&name) {
+            Some(current) if current.config() == &source.value => continue,
+            Some(current) => {
+                // Keep the connections while the source is restarted
+                current.stop().await;
+                state.sources.remove(&name);
+            }
+            None => {}
+        }
+
+        if let Err(err) = add_source(state, source) {
+            error!("Failed to create source {}: {:#}", name, err);
+        }
+    }
+
+    info!("Configuration reloaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::config::Overflow;
+    use crate::sink;
+    use crate::switcher::Switcher;
+
+    /// Measures the cost of distributing samples from 8 sources to 8 sinks.
+    ///
+    /// Run with `cargo test --release fan_out -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn fan_out() {
+        const SOURCES: usize = 8;
+        const SINKS: usize = 8;
+        const ROUNDS: usize = 10000;
+
+        let broadcasters = (0..SOURCES)
+            .map(|_| Broadcaster::default())
+            .collect::<Vec<_>>();
+
+        let mut receivers = Vec::new();
+        let mut controls = Vec::new();
+        for sink in 0..SINKS {
+            let (sender, receiver) = sink::buffer(Overflow::DropNewest);
+            let mut switcher = Switcher::new(sender);
+
+            for (source, broadcaster) in broadcasters.iter().enumerate() {
+                let (port, control) = switcher.port(|_| {});
+                broadcaster.insert(Arc::new(format!("sink{}", sink)), port);
+
+                // Every sink plays a different source
+                if source == sink % SOURCES {
+                    control.switch();
+                }
+                controls.push(control);
+            }
+
+            receivers.push(receiver);
+        }
+
+        let block = (0..1024).map(|i| i as i16).collect::<Vec<_>>();
+        let mut data = vec![0i16; block.len()];
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for broadcaster in broadcasters.iter() {
+                broadcaster.clone().data(&block);
+            }
+
+            for receiver in receivers.iter_mut() {
+                receiver.fill(&mut data);
+            }
+        }
+        let elapsed = start.elapsed();
+
+        let samples = (ROUNDS * SINKS * block.len()) as f64;
+        println!(
+            "{} sources x {} sinks: {:.2}ns per sample played",
+            SOURCES,
+            SINKS,
+            elapsed.as_nanos() as f64 / samples
+        );
+    }
+}
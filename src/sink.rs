@@ -1,34 +1,119 @@
-use std::any::Any;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{
+    AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use ringbuf::producer::PostponedProducer;
-use ringbuf::{HeapRb, Rb};
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb, Rb};
+use tokio::sync::Notify;
+use tracing::warn;
 
 use crate::config;
-use crate::config::Named;
+use crate::config::{Named, Overflow};
 use crate::device::Device;
+use crate::events::{Event, Events};
 use crate::pipe::Pipe;
+use crate::proto::State;
+use crate::supervisor::{Health, Reporter, Supervised, Worker};
 use crate::switcher::{Control, Port, Switcher};
 
-pub trait SinkStream: Any {}
+/// Maximum time a sender waits for the sink to make room with the `Block` overflow policy
+const BLOCK_TIMEOUT: Duration = Duration::from_millis(2);
+
+/// Maximum delay in milliseconds which can be applied to a sink
+pub const MAX_LATENCY: u32 = 1000;
+
+/// Number of samples kept free in the sink buffer for incoming data, in addition to the delay
+const HEADROOM: usize = 48000 * 2;
+
+/// Interval in which xrun statistics are logged
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of steps used to fade out a sink
+const FADE_STEPS: u32 = 25;
+
+pub trait SinkStream: Send + 'static {
+    /// Stops the stream and waits until it has finished.
+    fn stop(self: Box<Self>) -> BoxFuture<'static, ()>;
+}
+
+impl Worker for Box<dyn SinkStream> {
+    fn stop(self) -> BoxFuture<'static, ()> {
+        return SinkStream::stop(self);
+    }
+}
 
 pub struct Sink {
+    #[allow(unused)]
     pub kind: &'static str,
 
+    name: Arc<String>,
+
+    config: config::Output,
+
     muted: Arc<AtomicBool>,
     volume: Arc<AtomicU8>,
+    latency: Arc<AtomicU32>,
+
+    /// Additional attenuation applied on top of the volume while fading out
+    fade: Arc<AtomicU8>,
+
+    stats: Arc<Stats>,
+
+    stream: Supervised,
 
-    //stream: Box<dyn SinkStream>,
     switcher: Switcher<Sender>,
 
     sources: HashMap<Arc<String>, Control<Sender>>,
+
+    events: Events,
+}
+
+/// Counters for samples lost between sources and a sink.
+#[derive(Default)]
+pub struct Stats {
+    dropped: AtomicU64,
+    padded: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+impl Stats {
+    /// Number of samples discarded because the sink buffer was full.
+    pub fn dropped(&self) -> u64 {
+        return self.dropped.load(Ordering::Relaxed);
+    }
+
+    /// Number of silent samples inserted because the sink buffer was empty.
+    pub fn padded(&self) -> u64 {
+        return self.padded.load(Ordering::Relaxed);
+    }
+
+    /// Number of times a sender gave up waiting for room with the `Block` overflow policy.
+    pub fn timeouts(&self) -> u64 {
+        return self.timeouts.load(Ordering::Relaxed);
+    }
 }
 
 pub struct Sender {
-    tx: PostponedProducer<i16, Arc<HeapRb<i16>>>,
+    tx: HeapProducer<i16>,
+
+    wakeup: Arc<Notify>,
+
+    overflow: Overflow,
+
+    /// Samples held back until the receiver has discarded older ones (`DropOldest` policy only)
+    backlog: VecDeque<i16>,
+
+    /// Number of samples the receiver should discard before reading
+    discard: Arc<AtomicUsize>,
+
+    /// Number of channels of the sink, samples are only ever written and dropped in whole frames
+    channels: Arc<AtomicU16>,
+
+    stats: Arc<Stats>,
 
     muted: Arc<AtomicBool>,
     volume: Arc<AtomicU8>,
@@ -39,78 +124,367 @@ impl Sender {
         let muted = self.muted.load(Ordering::Relaxed);
         let volume = self.volume.load(Ordering::Relaxed);
 
-        for &sample in data {
-            let sample = if muted {
-                0
-            } else {
-                (sample as i32 * volume as i32 / u8::MAX as i32) as i16
-            };
+        let gain = if muted { 0 } else { q15(volume) };
+
+        let frame = self.channels.load(Ordering::Relaxed).max(1) as usize;
+
+        if self.overflow == Overflow::DropOldest && !self.backlog.is_empty() {
+            // Older samples from the previous round must go first
+            let len = usize::min(self.tx.free_len(), self.backlog.len());
+            let len = len - len % frame;
+
+            let (head, tail) = self.backlog.as_slices();
+            let split = usize::min(len, head.len());
+            self.tx.push_slice(&head[..split]);
+            self.tx.push_slice(&tail[..len - split]);
+
+            self.backlog.drain(..len);
+        }
+
+        let mut data = data;
+        if self.backlog.is_empty() {
+            data = &data[self.push(data, gain, frame)..];
+        }
+
+        self.wakeup.notify_one();
+
+        if data.is_empty() {
+            return;
+        }
+
+        match self.overflow {
+            Overflow::DropNewest => {}
+
+            Overflow::DropOldest => {
+                // Let the receiver make room and keep the overflowing samples for the next round
+                self.discard.fetch_add(data.len(), Ordering::Relaxed);
+
+                for chunk in data.chunks(frame) {
+                    if self.backlog.len() + chunk.len() > self.backlog.capacity() {
+                        let len = usize::min(frame, self.backlog.len());
+                        self.backlog.drain(..len);
+                        self.stats.dropped.fetch_add(len as u64, Ordering::Relaxed);
+                    }
+
+                    self.backlog
+                        .extend(chunk.iter().map(|&sample| apply_gain(sample, gain)));
+                }
+
+                return;
+            }
+
+            Overflow::Block => {
+                // Spin instead of sleeping, as the sender may run on a realtime audio thread
+                let deadline = Instant::now() + BLOCK_TIMEOUT;
+                while !data.is_empty() {
+                    if Instant::now() >= deadline {
+                        self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+
+                    std::thread::yield_now();
+                    data = &data[self.push(data, gain, frame)..];
+                }
+            }
+        }
+
+        self.stats
+            .dropped
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Writes as many samples as fit directly into the free space of the buffer, applying the
+    /// gain on the way, and returns the number of samples written.
+    ///
+    /// Only whole frames are written, so a full buffer never splits a frame.
+    fn push(&mut self, data: &[i16], gain: i32, frame: usize) -> usize {
+        let (head, tail) = unsafe { self.tx.free_space_as_slices() };
+
+        let total = usize::min(head.len() + tail.len(), data.len());
+        let data = &data[..total - total % frame];
+
+        let mut count = 0;
+        for free in [head, tail] {
+            let chunk = &data[count..];
+            let len = usize::min(free.len(), chunk.len());
+
+            for (slot, &sample) in free[..len].iter_mut().zip(&chunk[..len]) {
+                slot.write(apply_gain(sample, gain));
+            }
+
+            count += len;
+        }
+
+        // All slots up to count have been initialized above
+        unsafe { self.tx.advance(count) };
+
+        return count;
+    }
+}
+
+/// Converts a volume to a gain as Q15 fixed point - full volume maps to exactly 1.0.
+fn q15(volume: u8) -> i32 {
+    return volume as i32 * (1 << 15) / u8::MAX as i32;
+}
+
+#[inline(always)]
+fn apply_gain(sample: i16, gain: i32) -> i16 {
+    return ((sample as i32 * gain) >> 15) as i16;
+}
+
+/// The receiving end shared between subsequent instances of a restarted sink stream.
+pub type SharedReceiver = Arc<tokio::sync::Mutex<Receiver>>;
+
+pub struct Receiver {
+    rx: HeapConsumer<i16>,
+
+    wakeup: Arc<Notify>,
+
+    discard: Arc<AtomicUsize>,
+
+    stats: Arc<Stats>,
+
+    /// Requested delay in milliseconds
+    latency: Arc<AtomicU32>,
+
+    /// Additional attenuation applied on top of the volume while fading out
+    ///
+    /// This is applied when reading, so it also covers the samples waiting in the buffer.
+    fade: Arc<AtomicU8>,
+
+    /// Delay in samples currently applied, which is always backed by samples in the buffer
+    delay: usize,
+
+    rate: u32,
+    channels: Arc<AtomicU16>,
+}
+
+impl Receiver {
+    /// Sets the format of the data consumed by the sink, used to convert the delay into samples.
+    pub fn set_format(&mut self, rate: u32, channels: u16) {
+        self.rate = rate;
+        self.channels.store(channels, Ordering::Relaxed);
+    }
+
+    /// Reads available samples into `data` and returns the number of samples read.
+    fn pop_slice(&mut self, data: &mut [i16]) -> usize {
+        let discard = self.discard.swap(0, Ordering::Relaxed);
+        if discard > 0 {
+            let skipped = self.rx.skip(discard);
+            self.stats
+                .dropped
+                .fetch_add(skipped as u64, Ordering::Relaxed);
+
+            self.delay = usize::min(self.delay, self.rx.len());
+        }
+
+        // The delay line works by inserting silence, which lets the buffer fill up by the same
+        // amount. Decreasing the delay skips the surplus again.
+        let latency = self.latency.load(Ordering::Relaxed) as usize;
+        let channels = self.channels.load(Ordering::Relaxed) as usize;
+        let target = latency * self.rate as usize / 1000 * channels;
+
+        // The buffer is sized for 48kHz stereo - formats with more samples per second get a
+        // shorter maximum delay
+        let limit = self.rx.capacity() - HEADROOM;
+        let target = usize::min(target, limit - limit % channels.max(1));
+
+        let mut inserted = 0;
+        if target > self.delay {
+            // Silence only delays the samples already waiting in the buffer
+            inserted = usize::min(target - self.delay, data.len());
+            inserted = usize::min(inserted, self.rx.len());
+            data[..inserted].fill(0i16);
+            self.delay += inserted;
+        } else if target < self.delay {
+            self.delay -= self.rx.skip(self.delay - target);
+        }
+
+        let r = self.rx.pop_slice(&mut data[inserted..]);
+
+        // Running out of samples plays out the delay, it must be built up again
+        if r < data.len() - inserted {
+            self.delay = 0;
+        }
+
+        return inserted + r;
+    }
+
+    /// Fills `data` completely, padding with silence if not enough samples are available.
+    pub fn fill(&mut self, data: &mut [i16]) {
+        let r = self.pop_slice(data);
+        self.pad(data, r);
+        self.apply_fade(data);
+    }
+
+    /// Fills `data` completely, waiting for the sources until `deadline` before padding with
+    /// silence.
+    pub async fn fill_until(&mut self, data: &mut [i16], deadline: tokio::time::Instant) {
+        let mut r = self.pop_slice(data);
+        while r < data.len() {
+            if tokio::time::timeout_at(deadline, self.wakeup.notified())
+                .await
+                .is_err()
+            {
+                break;
+            }
+
+            r += self.pop_slice(&mut data[r..]);
+        }
+
+        self.pad(data, r);
+        self.apply_fade(data);
+    }
+
+    fn apply_fade(&self, data: &mut [i16]) {
+        let fade = self.fade.load(Ordering::Relaxed);
+        if fade == u8::MAX {
+            return;
+        }
 
-            let _ = self.tx.push(sample);
+        let gain = q15(fade);
+        for sample in data.iter_mut() {
+            *sample = apply_gain(*sample, gain);
         }
+    }
+
+    fn pad(&mut self, data: &mut [i16], r: usize) {
+        data[r..].fill(0i16);
 
-        self.tx.sync();
+        // Getting no data at all means no source is playing, which is not an underrun
+        if r > 0 && r < data.len() {
+            self.stats
+                .padded
+                .fetch_add((data.len() - r) as u64, Ordering::Relaxed);
+        }
     }
 }
 
+/// Creates the buffer between the sources of a sink and its stream, playing at full volume
+/// without delay.
+pub(crate) fn buffer(overflow: Overflow) -> (Sender, Receiver) {
+    let mut ring = HeapRb::<i16>::new(HEADROOM + (MAX_LATENCY as usize * 48 * 2));
+    for _ in 0..128 {
+        ring.push(0i16).expect("Fill ring buffer");
+    }
+
+    let (tx, rx) = ring.split();
+
+    let wakeup = Arc::new(Notify::new());
+    let discard = Arc::new(AtomicUsize::new(0));
+    let stats = Arc::new(Stats::default());
+    let channels = Arc::new(AtomicU16::new(2));
+
+    let sender = Sender {
+        tx,
+        wakeup: wakeup.clone(),
+        overflow,
+        backlog: VecDeque::with_capacity(match overflow {
+            Overflow::DropOldest => 4096,
+            _ => 0,
+        }),
+        discard: discard.clone(),
+        channels: channels.clone(),
+        stats: stats.clone(),
+        muted: Arc::new(AtomicBool::new(false)),
+        volume: Arc::new(AtomicU8::new(u8::MAX)),
+    };
+
+    let receiver = Receiver {
+        rx,
+        wakeup,
+        discard,
+        stats,
+        latency: Arc::new(AtomicU32::new(0)),
+        fade: Arc::new(AtomicU8::new(u8::MAX)),
+        delay: 0,
+        rate: 48000,
+        channels,
+    };
+
+    return (sender, receiver);
+}
+
 pub trait SinkType {
     type Config;
 
     type Stream: SinkStream;
 
+    /// Creates a stream instance. The sink type itself lives as long as the supervised sink.
     fn sink(
+        &self,
         name: &str,
-        config: Self::Config,
-        rx: ringbuf::HeapConsumer<i16>,
+        config: &Self::Config,
+        rx: SharedReceiver,
+        reporter: Reporter,
     ) -> Result<Self::Stream>;
 }
 
 impl Sink {
-    pub fn with_config(config: Named<config::Sink>) -> Result<(Named<Self>, Box<dyn SinkStream>)> {
+    pub fn with_config(config: Named<config::Output>, events: Events) -> Result<Named<Self>> {
         let (named, config) = config.take();
 
-        let kind = match &config {
+        let kind = match &config.sink {
             config::Sink::Device(_) => "device",
             config::Sink::Pipe(_) => "pipe",
         };
 
-        let mut ring = HeapRb::<i16>::new(48000 * 2);
-        for _ in 0..128 {
-            ring.push(0i16).expect("Fill ring buffer");
+        if config.latency > MAX_LATENCY {
+            bail!("Latency exceeds maximum of {}ms", MAX_LATENCY);
         }
 
-        let (tx, rx) = ring.split();
-
-        let stream = match config {
-            config::Sink::Pipe(config) => {
-                Box::new(Pipe::sink(named.name(), config, rx)?) as Box<dyn SinkStream>
-            }
-            config::Sink::Device(config) => {
-                Box::new(Device::sink(named.name(), config, rx)?) as Box<dyn SinkStream>
-            }
-        };
-
-        let muted = Arc::new(AtomicBool::new(false));
-        let volume = Arc::new(AtomicU8::new(u8::MAX));
-
-        let sender = Sender {
-            tx: tx.into_postponed(),
-            muted: muted.clone(),
-            volume: volume.clone(),
-        };
+        let (sender, rx) = buffer(config.overflow);
+
+        let muted = sender.muted.clone();
+        let volume = sender.volume.clone();
+        let fade = rx.fade.clone();
+        let stats = sender.stats.clone();
+        let latency = rx.latency.clone();
+
+        muted.store(config.muted, Ordering::Relaxed);
+        volume.store(
+            (config.volume as u32 * u8::MAX as u32 / 100) as u8,
+            Ordering::Relaxed,
+        );
+        latency.store(config.latency, Ordering::Relaxed);
+
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+
+        let name = named.name.clone();
+        let sink = config.sink.clone();
+        let pipe = Pipe::default();
+        let stream = Supervised::start(named.name.clone(), move |reporter| {
+            let rx = rx.clone();
+            return Ok(match &sink {
+                config::Sink::Pipe(config) => {
+                    Box::new(pipe.sink(&name, config, rx, reporter)?) as Box<dyn SinkStream>
+                }
+                config::Sink::Device(config) => {
+                    Box::new(Device.sink(&name, config, rx, reporter)?) as Box<dyn SinkStream>
+                }
+            });
+        });
 
         let switcher = Switcher::new(sender);
 
-        return Ok((
-            named.with(Sink {
-                kind,
-                muted,
-                volume,
-                switcher,
-                sources: HashMap::new(),
-            }),
+        return Ok(named.with(Sink {
+            kind,
+            name: named.name.clone(),
+            config,
+            muted,
+            volume,
+            latency,
+            fade,
+            stats,
             stream,
-        ));
+            switcher,
+            sources: HashMap::new(),
+            events,
+        }));
+    }
+
+    pub fn config(&self) -> &config::Output {
+        return &self.config;
     }
 
     pub fn muted(&self) -> bool {
@@ -121,12 +495,58 @@ impl Sink {
         return self.volume.load(Ordering::Relaxed);
     }
 
+    pub fn latency(&self) -> u32 {
+        return self.latency.load(Ordering::Relaxed);
+    }
+
+    pub fn health(&self) -> &Arc<Health> {
+        return self.stream.health();
+    }
+
+    pub fn stats(&self) -> &Arc<Stats> {
+        return &self.stats;
+    }
+
     pub fn set_muted(&mut self, muted: bool) {
-        self.muted.store(muted, Ordering::Relaxed);
+        if self.muted.swap(muted, Ordering::Relaxed) != muted {
+            self.events.emit(Event::Muted {
+                sink: self.name.clone(),
+                muted,
+            });
+        }
     }
 
     pub fn set_volume(&mut self, volume: u8) {
-        self.volume.store(volume, Ordering::Relaxed);
+        if self.volume.swap(volume, Ordering::Relaxed) != volume {
+            self.events.emit(Event::Volume {
+                sink: self.name.clone(),
+                volume,
+            });
+        }
+    }
+
+    pub fn set_latency(&mut self, latency: u32) -> Result<()> {
+        if latency > MAX_LATENCY {
+            bail!("Latency exceeds maximum of {}ms", MAX_LATENCY);
+        }
+
+        self.latency.store(latency, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    /// Ramps the output down to silence over the given duration.
+    pub async fn fade_out(&self, duration: Duration) {
+        for step in (0..FADE_STEPS).rev() {
+            self.fade.store(
+                (u8::MAX as u32 * step / FADE_STEPS) as u8,
+                Ordering::Relaxed,
+            );
+            tokio::time::sleep(duration / FADE_STEPS).await;
+        }
+    }
+
+    pub async fn stop(&mut self) {
+        self.stream.stop().await;
     }
 
     pub fn get_source(&mut self, name: &Arc<String>) -> Option<&Control<Sender>> {
@@ -144,8 +564,172 @@ impl Sink {
     }
 
     pub fn add_source(&mut self, name: Arc<String>) -> Port<Sender> {
-        let (port, control) = self.switcher.port();
+        let events = self.events.clone();
+        let sink = self.name.clone();
+        let source = name.clone();
+
+        let (port, control) = self.switcher.port(move |active| {
+            events.emit(Event::Switched {
+                sink: sink.clone(),
+                source: active.then(|| source.clone()),
+            });
+        });
         self.sources.insert(name, control);
         return port;
     }
+
+    /// Removes the source, leaving the sink silent if it was the active one.
+    pub fn remove_source(&mut self, name: &Arc<String>) {
+        if let Some(control) = self.sources.remove(name) {
+            control.release();
+        }
+    }
+}
+
+/// Periodically logs the samples lost by each sink since the last report.
+///
+/// Logging from the audio path itself is avoided; this keeps the log rate bounded to one line per
+/// sink and interval, regardless of how often samples are dropped.
+pub async fn report(state: Arc<tokio::sync::Mutex<State>>) {
+    let mut last = HashMap::<Arc<String>, (Arc<Stats>, (u64, u64, u64))>::new();
+
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + REPORT_INTERVAL,
+        REPORT_INTERVAL,
+    );
+    loop {
+        interval.tick().await;
+
+        let sinks = state
+            .lock()
+            .await
+            .sinks
+            .iter()
+            .map(|(name, sink)| (name.clone(), sink.stats().clone()))
+            .collect::<Vec<_>>();
+
+        // Sinks which have been added or recreated start from zero
+        last.retain(|name, (stats, _)| {
+            sinks
+                .iter()
+                .any(|(n, s)| n == name && Arc::ptr_eq(s, stats))
+        });
+
+        for (name, stats) in sinks {
+            let (_, last) = last
+                .entry(name.clone())
+                .or_insert_with(|| (stats.clone(), (0, 0, 0)));
+
+            let current = (stats.dropped(), stats.padded(), stats.timeouts());
+
+            if current != *last {
+                warn!(
+                    "Sink {}: {} samples dropped (overflow, {} timeouts), {} samples padded (underflow) in the last {}s",
+                    name,
+                    current.0 - last.0,
+                    current.2 - last.2,
+                    current.1 - last.1,
+                    REPORT_INTERVAL.as_secs(),
+                );
+            }
+
+            *last = current;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receiver(latency: u32) -> (HeapProducer<i16>, Receiver) {
+        let (tx, rx) = HeapRb::<i16>::new(HEADROOM + MAX_LATENCY as usize * 48 * 2).split();
+
+        let rx = Receiver {
+            rx,
+            wakeup: Arc::new(Notify::new()),
+            discard: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(Stats::default()),
+            latency: Arc::new(AtomicU32::new(latency)),
+            fade: Arc::new(AtomicU8::new(u8::MAX)),
+            delay: 0,
+            rate: 48000,
+            channels: Arc::new(AtomicU16::new(2)),
+        };
+
+        return (tx, rx);
+    }
+
+    /// Feeds `rounds` blocks of increasing samples and returns everything played.
+    fn play(tx: &mut HeapProducer<i16>, rx: &mut Receiver, rounds: usize) -> Vec<i16> {
+        let mut played = Vec::new();
+        let mut data = [0i16; 256];
+        for round in 0..rounds {
+            for i in 0..data.len() {
+                tx.push((round * data.len() + i + 1) as i16)
+                    .expect("Buffer has room");
+            }
+
+            rx.fill(&mut data);
+            played.extend_from_slice(&data);
+        }
+
+        return played;
+    }
+
+    #[test]
+    fn delay_is_not_consumed_while_idle() {
+        let (mut tx, mut rx) = receiver(10);
+
+        let mut data = [0i16; 256];
+        for _ in 0..100 {
+            assert_eq!(rx.pop_slice(&mut data), 0);
+        }
+
+        let played = play(&mut tx, &mut rx, 20);
+        assert_eq!(played.iter().position(|&s| s != 0), Some(960));
+        assert_eq!(
+            played[960..],
+            (1..=played.len() as i16 - 960).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn delay_is_rebuilt_after_underrun() {
+        let (mut tx, mut rx) = receiver(10);
+
+        play(&mut tx, &mut rx, 20);
+        assert_eq!(rx.delay, 960);
+
+        // Drain the buffer completely
+        let mut data = [0i16; 2048];
+        rx.fill(&mut data);
+        assert_eq!(rx.delay, 0);
+
+        let played = play(&mut tx, &mut rx, 20);
+        assert_eq!(played.iter().position(|&s| s != 0), Some(960));
+        assert_eq!(rx.delay, 960);
+    }
+
+    #[test]
+    fn fade_covers_buffered_samples() {
+        let (mut sender, mut rx) = buffer(Overflow::DropNewest);
+
+        sender.send(&[1000i16; 4096]);
+        rx.fade.store(0, Ordering::Relaxed);
+
+        let mut data = [1i16; 1024];
+        rx.fill(&mut data);
+        assert!(data.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn block_gives_up_when_full() {
+        let (mut sender, _rx) = buffer(Overflow::Block);
+
+        let data = vec![1i16; sender.tx.capacity()];
+        sender.send(&data);
+        assert_eq!(sender.stats.timeouts(), 1);
+        assert_eq!(sender.stats.dropped(), 128);
+    }
 }
@@ -1,22 +1,37 @@
-use std::any::Any;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
+use futures::future::BoxFuture;
 use url::Url;
 
 use crate::config;
 use crate::config::Named;
 use crate::device::Device;
+use crate::events::{Event, Events};
 use crate::pipe::Pipe;
+use crate::supervisor::{Health, Reporter, Supervised, Worker};
 
-pub trait SourceStream: Any {}
+pub trait SourceStream: Send + 'static {
+    /// Stops the stream and waits until it has finished.
+    fn stop(self: Box<Self>) -> BoxFuture<'static, ()>;
+}
+
+impl Worker for Box<dyn SourceStream> {
+    fn stop(self) -> BoxFuture<'static, ()> {
+        return SourceStream::stop(self);
+    }
+}
 
 pub struct Source {
     #[allow(unused)]
     kind: &'static str,
 
+    config: config::Source,
+
     active: Arc<AtomicBool>,
+
+    stream: Supervised,
 }
 
 pub trait SourceCallback: Send {
@@ -29,18 +44,22 @@ pub trait SourceType {
 
     type Stream: SourceStream;
 
+    /// Creates a stream instance. The source type itself lives as long as the supervised source.
     fn source(
+        &self,
         name: &str,
-        config: Self::Config,
+        config: &Self::Config,
         callback: impl SourceCallback + 'static,
+        reporter: Reporter,
     ) -> Result<Self::Stream>;
 }
 
 impl Source {
     pub fn with_config(
         config: Named<config::Source>,
-        callback: impl SourceCallback + 'static,
-    ) -> Result<(Named<Self>, Box<dyn SourceStream>)> {
+        callback: impl SourceCallback + Clone + 'static,
+        events: Events,
+    ) -> Result<Named<Self>> {
         let (named, config) = config.take();
 
         let kind = match &config {
@@ -52,19 +71,38 @@ impl Source {
 
         let callback = MonitoringSourceCallback {
             inner: callback,
+            name: named.name.clone(),
             active: active.clone(),
+            events,
         };
 
-        let stream = match config {
-            config::Source::Pipe(config) => {
-                Box::new(Pipe::source(named.name(), config, callback)?) as Box<dyn SourceStream>
-            }
-            config::Source::Device(config) => {
-                Box::new(Device::source(named.name(), config, callback)?) as Box<dyn SourceStream>
-            }
-        };
+        let name = named.name.clone();
+        let source = config.clone();
+        let pipe = Pipe::default();
+        let stream = Supervised::start(named.name.clone(), move |reporter| {
+            let callback = callback.clone();
+            return Ok(match &source {
+                config::Source::Pipe(config) => {
+                    Box::new(pipe.source(&name, config, callback, reporter)?)
+                        as Box<dyn SourceStream>
+                }
+                config::Source::Device(config) => {
+                    Box::new(Device.source(&name, config, callback, reporter)?)
+                        as Box<dyn SourceStream>
+                }
+            });
+        });
+
+        return Ok(named.with(Self {
+            kind,
+            config,
+            active,
+            stream,
+        }));
+    }
 
-        return Ok((named.with(Self { kind, active }), stream));
+    pub fn config(&self) -> &config::Source {
+        return &self.config;
     }
 
     pub fn uri(&self) -> Url {
@@ -74,27 +112,50 @@ impl Source {
     pub fn is_active(&self) -> bool {
         return self.active.load(Ordering::Relaxed);
     }
+
+    pub fn health(&self) -> &Arc<Health> {
+        return self.stream.health();
+    }
+
+    pub async fn stop(&mut self) {
+        self.stream.stop().await;
+    }
 }
 
+#[derive(Clone)]
 struct MonitoringSourceCallback<C: SourceCallback> {
     inner: C,
+    name: Arc<String>,
     active: Arc<AtomicBool>,
+    events: Events,
+}
+
+impl<C: SourceCallback> MonitoringSourceCallback<C> {
+    fn set_active(&self, active: bool) {
+        // Only transitions are emitted, keeping the audio path free of events otherwise
+        if self.active.swap(active, Ordering::Relaxed) != active {
+            self.events.emit(Event::Activity {
+                source: self.name.clone(),
+                active,
+            });
+        }
+    }
 }
 
 impl<C: SourceCallback> Drop for MonitoringSourceCallback<C> {
     fn drop(&mut self) {
-        self.active.store(false, Ordering::Relaxed);
+        self.set_active(false);
     }
 }
 
 impl<C: SourceCallback> SourceCallback for MonitoringSourceCallback<C> {
     fn data(&mut self, data: &[i16]) {
-        self.active.store(true, Ordering::Relaxed);
+        self.set_active(true);
         self.inner.data(data);
     }
 
     fn idle(&mut self) {
-        self.active.store(false, Ordering::Relaxed);
+        self.set_active(false);
         self.inner.idle();
     }
 }
@@ -0,0 +1,226 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Delay before the first restart attempt
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Upper limit for the delay between restart attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A stream running at least this long is considered stable and resets the backoff
+const STABLE: Duration = Duration::from_secs(60);
+
+/// A stream which can be stopped gracefully.
+pub trait Worker: Send + 'static {
+    /// Stops the stream and waits until it has finished.
+    fn stop(self) -> BoxFuture<'static, ()>;
+}
+
+/// Health of a supervised stream.
+#[derive(Default)]
+pub struct Health {
+    failed: AtomicBool,
+    restarts: AtomicU32,
+    last_error: Mutex<Option<String>>,
+
+    /// Incremented for each stream instance to ignore reports from replaced instances
+    generation: AtomicU64,
+
+    notify: Notify,
+}
+
+impl Health {
+    /// Whether the stream is currently running.
+    pub fn is_healthy(&self) -> bool {
+        return !self.failed.load(Ordering::Relaxed);
+    }
+
+    /// Number of times the stream has been restarted.
+    pub fn restarts(&self) -> u32 {
+        return self.restarts.load(Ordering::Relaxed);
+    }
+
+    /// The last error reported by the stream, if any.
+    pub fn last_error(&self) -> Option<String> {
+        return self.last_error.lock().clone();
+    }
+
+    fn record(&self, error: impl Display) {
+        self.failed.store(true, Ordering::Relaxed);
+        *self.last_error.lock() = Some(format!("{:#}", error));
+    }
+}
+
+/// Handle given to a stream to report failures to its supervisor.
+#[derive(Clone)]
+pub struct Reporter {
+    health: Arc<Health>,
+    generation: u64,
+}
+
+impl Reporter {
+    pub fn fail(&self, error: impl Display) {
+        if self.health.generation.load(Ordering::Relaxed) != self.generation {
+            return;
+        }
+
+        self.health.record(error);
+        self.health.notify.notify_one();
+    }
+}
+
+/// A stream which is recreated with exponential backoff whenever it fails.
+///
+/// The stream is stopped by dropping it when the supervised handle is dropped.
+pub struct Supervised {
+    health: Arc<Health>,
+    token: CancellationToken,
+
+    task: JoinHandle<()>,
+}
+
+impl Supervised {
+    /// Creates the stream using `factory` and starts supervising it.
+    ///
+    /// A failure to create the initial stream is handled like any later failure - the stream
+    /// starts out as failed and is restarted.
+    pub fn start<S, F>(name: Arc<String>, mut factory: F) -> Self
+    where
+        S: Worker,
+        F: FnMut(Reporter) -> Result<S> + Send + 'static,
+    {
+        let health = Arc::new(Health::default());
+
+        let stream = match factory(Reporter {
+            health: health.clone(),
+            generation: 0,
+        }) {
+            Ok(stream) => Some(stream),
+            Err(err) => {
+                error!("Failed to start stream {}: {:#}", name, err);
+                health.record(err);
+                None
+            }
+        };
+
+        let token = CancellationToken::new();
+
+        let task = tokio::spawn(supervise(
+            name,
+            factory,
+            stream,
+            health.clone(),
+            token.clone(),
+        ));
+
+        return Self {
+            health,
+            token,
+            task,
+        };
+    }
+
+    pub fn health(&self) -> &Arc<Health> {
+        return &self.health;
+    }
+
+    /// Stops the supervision and waits until the stream has been stopped.
+    pub async fn stop(&mut self) {
+        self.token.cancel();
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for Supervised {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+async fn supervise<S, F>(
+    name: Arc<String>,
+    mut factory: F,
+    mut stream: Option<S>,
+    health: Arc<Health>,
+    token: CancellationToken,
+) where
+    S: Worker,
+    F: FnMut(Reporter) -> Result<S>,
+{
+    let mut started = Instant::now();
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        // A stream which failed to start is restarted right away
+        if stream.is_some() {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = health.notify.notified() => {},
+            }
+
+            // Ignore reports which arrived while the stream got replaced
+            if health.is_healthy() {
+                continue;
+            }
+
+            // Stop the failed instance before creating a new one
+            if let Some(stream) = stream.take() {
+                stream.stop().await;
+            }
+
+            if started.elapsed() > STABLE {
+                backoff = MIN_BACKOFF;
+            }
+
+            warn!(
+                "Stream {} failed: {}",
+                name,
+                health.last_error().unwrap_or_default()
+            );
+        }
+
+        while stream.is_none() {
+            info!("Restarting stream {} in {:?}", name, backoff);
+
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = tokio::time::sleep(backoff) => {},
+            }
+
+            backoff = Duration::min(backoff * 2, MAX_BACKOFF);
+
+            let generation = health.generation.fetch_add(1, Ordering::Relaxed) + 1;
+            health.restarts.fetch_add(1, Ordering::Relaxed);
+            health.failed.store(false, Ordering::Relaxed);
+
+            match factory(Reporter {
+                health: health.clone(),
+                generation,
+            }) {
+                Ok(s) => {
+                    stream = Some(s);
+                    started = Instant::now();
+                }
+                Err(err) => {
+                    error!("Failed to restart stream {}: {:#}", name, err);
+                    health.record(err);
+                }
+            }
+        }
+    }
+
+    if let Some(stream) = stream {
+        stream.stop().await;
+    }
+}
@@ -1,87 +1,276 @@
-use parking_lot::lock_api::ArcMutexGuard;
-use parking_lot::{Mutex, RawMutex};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+/// Marker for no port being active
+const NONE: usize = usize::MAX;
+
 struct SwitcherInner<T> {
-    current: Mutex<Arc<Mutex<Option<T>>>>,
+    /// ID of the currently active port
+    active: AtomicUsize,
+
+    /// Set while a port is accessing the value
+    busy: AtomicBool,
+
+    value: UnsafeCell<T>,
 }
 
+// The value is only ever accessed by the port holding the busy flag
+unsafe impl<T: Send> Sync for SwitcherInner<T> {}
+
 pub struct Switcher<T> {
     inner: Arc<SwitcherInner<T>>,
+
+    ports: usize,
 }
 
 impl<T> Switcher<T> {
     pub fn new(value: T) -> Self {
         return Self {
             inner: Arc::new(SwitcherInner {
-                current: Mutex::new(Arc::new(Mutex::new(Some(value)))),
+                active: AtomicUsize::new(NONE),
+                busy: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
             }),
+            ports: 0,
         };
     }
 
-    pub fn port(&mut self) -> (Port<T>, Control<T>) {
-        let value = Arc::new(Mutex::new(None));
+    /// Adds a port, calling `observer` whenever its control activates or releases it.
+    pub fn port(
+        &mut self,
+        observer: impl Fn(bool) + Send + Sync + 'static,
+    ) -> (Port<T>, Control<T>) {
+        let id = self.ports;
+        self.ports += 1;
 
         let port = Port {
-            value: value.clone(),
+            switcher: self.inner.clone(),
+            id,
         };
 
         let control = Control {
             switcher: self.inner.clone(),
-            port: value,
+            id,
+            observer: Box::new(observer),
         };
 
         return (port, control);
     }
 }
 
-#[derive(Clone)]
 pub struct Port<T> {
-    value: Arc<Mutex<Option<T>>>,
+    switcher: Arc<SwitcherInner<T>>,
+    id: usize,
 }
 
-impl<'s, T> Port<T> {
-    pub fn access(&self) -> AccessGuard<T> {
-        return self.value.lock_arc();
+// Not derived as the value itself does not need to be cloneable
+impl<T> Clone for Port<T> {
+    fn clone(&self) -> Self {
+        return Self {
+            switcher: self.switcher.clone(),
+            id: self.id,
+        };
     }
 }
 
-pub type AccessGuard<T> = ArcMutexGuard<RawMutex, Option<T>>;
+impl<T> Port<T> {
+    /// Gives access to the value if this port is the active one.
+    ///
+    /// This never takes a lock. Inactive ports return after a single atomic load. The active port
+    /// only has to wait if the previously active port is still finishing its access right after a
+    /// switch. This wait is bounded by a single access of the previous port, so samples of the
+    /// active port are never dropped.
+    pub fn access(&self) -> Option<AccessGuard<'_, T>> {
+        if self.switcher.active.load(Ordering::Acquire) != self.id {
+            return None;
+        }
+
+        // Wait for the previously active port to release the value
+        while self
+            .switcher
+            .busy
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Stop waiting if another port has been activated in the meantime
+            if self.switcher.active.load(Ordering::Acquire) != self.id {
+                return None;
+            }
+
+            std::hint::spin_loop();
+        }
+
+        // The port may have been deactivated while waiting
+        if self.switcher.active.load(Ordering::Acquire) != self.id {
+            self.switcher.busy.store(false, Ordering::Release);
+            return None;
+        }
+
+        return Some(AccessGuard {
+            switcher: &self.switcher,
+        });
+    }
+}
+
+pub struct AccessGuard<'p, T> {
+    switcher: &'p SwitcherInner<T>,
+}
+
+impl<T> Deref for AccessGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        return unsafe { &*self.switcher.value.get() };
+    }
+}
+
+impl<T> DerefMut for AccessGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        return unsafe { &mut *self.switcher.value.get() };
+    }
+}
+
+impl<T> Drop for AccessGuard<'_, T> {
+    fn drop(&mut self) {
+        self.switcher.busy.store(false, Ordering::Release);
+    }
+}
 
 pub struct Control<T> {
     switcher: Arc<SwitcherInner<T>>,
-    port: Arc<Mutex<Option<T>>>,
+    id: usize,
+
+    observer: Box<dyn Fn(bool) + Send + Sync>,
 }
 
 impl<T> Control<T> {
     pub fn switch(&self) {
-        // Find currently active port
-        let mut current = self.switcher.current.lock();
+        // Ports pick up the change on their next access
+        if self.switcher.active.swap(self.id, Ordering::AcqRel) != self.id {
+            (self.observer)(true);
+        }
+    }
+
+    /// Deactivates the port if it is the active one.
+    pub fn release(&self) {
+        if self
+            .switcher
+            .active
+            .compare_exchange(self.id, NONE, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            (self.observer)(false);
+        }
+    }
 
-        // Acquire lock in active port
-        let mut old = current.lock();
+    pub fn is_active(&self) -> bool {
+        return self.switcher.active.load(Ordering::Acquire) == self.id;
+    }
+}
 
-        // Remove value from active port
-        let value = old.take().expect("Current port holds value");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Drop lock
-        drop(old);
+    /// Marks the epoch in which ports are being switched.
+    const SWITCHING: usize = 0;
 
-        // Acquire lock in new port
-        let mut new = self.port.lock();
+    #[test]
+    fn switching_neither_loses_nor_duplicates_samples() {
+        const PRODUCERS: usize = 3;
+        const SWITCHES: usize = 2000;
 
-        // Set value to new port
-        new.replace(value);
+        let mut switcher = Switcher::new(Vec::<(usize, u64)>::new());
+        let (ports, controls): (Vec<_>, Vec<_>) =
+            (0..PRODUCERS).map(|_| switcher.port(|_| {})).unzip();
 
-        // Drop Lock
-        drop(new);
+        // Lower byte holds the active port plus one or SWITCHING, the rest counts the switches
+        let epoch = Arc::new(AtomicUsize::new(SWITCHING));
+        let running = Arc::new(AtomicBool::new(true));
 
-        // Update currently active port
-        *current = self.port.clone();
-    }
+        let producers = ports
+            .into_iter()
+            .enumerate()
+            .map(|(id, port)| {
+                let epoch = epoch.clone();
+                let running = running.clone();
+                return std::thread::spawn(move || {
+                    let mut seq = 0u64;
+                    let mut delivered = 0u64;
+                    while running.load(Ordering::Relaxed) {
+                        let before = epoch.load(Ordering::SeqCst);
 
-    pub fn is_active(&self) -> bool {
-        let current = self.switcher.current.lock();
-        return Arc::ptr_eq(&self.port, &*current);
+                        // Try each block once, like the broadcaster does
+                        let accepted = match port.access() {
+                            Some(mut value) => {
+                                value.push((id, seq));
+
+                                // Hold the value for a while to provoke contention
+                                std::thread::yield_now();
+
+                                true
+                            }
+                            None => false,
+                        };
+
+                        // The port has been active during the whole access
+                        let after = epoch.load(Ordering::SeqCst);
+                        if before == after && before & 0xff == id + 1 {
+                            assert!(accepted, "Block {} of active port {} lost", seq, id);
+                        }
+
+                        if accepted {
+                            delivered += 1;
+                        }
+
+                        seq += 1;
+                        std::thread::yield_now();
+                    }
+
+                    return delivered;
+                });
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..SWITCHES {
+            let id = i % PRODUCERS;
+
+            epoch.store((i + 1) << 8 | SWITCHING, Ordering::SeqCst);
+            controls[id].switch();
+            epoch.store((i + 1) << 8 | (id + 1), Ordering::SeqCst);
+
+            std::thread::yield_now();
+        }
+
+        running.store(false, Ordering::Relaxed);
+        let delivered = producers
+            .into_iter()
+            .map(|producer| producer.join().expect("Producer failed"))
+            .collect::<Vec<_>>();
+
+        drop(controls);
+        let samples = Arc::try_unwrap(switcher.inner)
+            .ok()
+            .expect("All ports are dropped")
+            .value
+            .into_inner();
+
+        // Every accepted block arrives exactly once and in order
+        let mut last = [None::<u64>; PRODUCERS];
+        let mut count = [0u64; PRODUCERS];
+        for (id, seq) in samples {
+            assert!(
+                last[id].is_none_or(|last| last < seq),
+                "Block {} of port {} duplicated or out of order",
+                seq,
+                id
+            );
+            last[id] = Some(seq);
+            count[id] += 1;
+        }
+
+        assert_eq!(count.as_slice(), delivered.as_slice());
     }
 }
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::os::fd::FromRawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+use sd_notify::NotifyState;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::proto::{Listener, State};
+
+fn notify(state: &[NotifyState]) {
+    // Does nothing if not running under systemd
+    if let Err(err) = sd_notify::notify(false, state) {
+        warn!("Failed to notify systemd: {}", err);
+    }
+}
+
+/// Tells systemd that the daemon has finished starting up.
+pub fn ready(status: &str) {
+    notify(&[NotifyState::Ready, NotifyState::Status(status)]);
+}
+
+/// Tells systemd that the daemon is reloading its configuration.
+pub fn reloading() {
+    match NotifyState::monotonic_usec_now() {
+        Ok(now) => notify(&[NotifyState::Reloading, now]),
+        Err(err) => warn!("Failed to get monotonic time: {}", err),
+    }
+}
+
+/// Tells systemd that the daemon is shutting down.
+pub fn stopping() {
+    notify(&[NotifyState::Stopping, NotifyState::Status("Shutting down")]);
+}
+
+/// Takes the control sockets passed by systemd socket activation, if any.
+pub fn listeners() -> Result<Vec<Listener>> {
+    let fds = sd_notify::listen_fds().context("Invalid socket activation environment")?;
+
+    return fds
+        .map(|fd| {
+            let family = getsockname::<SockaddrStorage>(fd)?.family();
+
+            // The descriptors are passed to us exclusively by systemd, which also owns the socket
+            // files
+            return Ok(if family == Some(AddressFamily::Unix) {
+                let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+                listener.set_nonblocking(true)?;
+                Listener::Unix(UnixListener::from_std(listener)?, None)
+            } else {
+                let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+                listener.set_nonblocking(true)?;
+                Listener::Tcp(TcpListener::from_std(listener)?)
+            });
+        })
+        .collect();
+}
+
+/// Sends keep-alive pings to the systemd watchdog, if enabled.
+///
+/// Pings are withheld once a stream has been failing for longer than the watchdog timeout, so
+/// systemd restarts the daemon if the supervisor can not recover a stream on its own.
+pub async fn watchdog(state: Arc<Mutex<State>>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    let timeout = Duration::from_micros(usec);
+    debug!("Systemd watchdog enabled with timeout {:?}", timeout);
+
+    let mut failing = HashMap::<Arc<String>, Instant>::new();
+
+    let mut interval = tokio::time::interval(timeout / 2);
+    loop {
+        interval.tick().await;
+
+        let now = Instant::now();
+
+        let state = state.lock().await;
+
+        let health = state
+            .sources
+            .iter()
+            .map(|(name, source)| (name, source.health()))
+            .chain(state.sinks.iter().map(|(name, sink)| (name, sink.health())));
+
+        // Keep track of when each stream started failing
+        failing = health
+            .filter(|(_, health)| !health.is_healthy())
+            .map(|(name, _)| (name.clone(), failing.get(name).copied().unwrap_or(now)))
+            .collect();
+
+        drop(state);
+
+        let mut unhealthy = failing.iter().collect::<Vec<_>>();
+        unhealthy.sort();
+
+        if unhealthy.is_empty() {
+            notify(&[NotifyState::Watchdog, NotifyState::Status("Running")]);
+            continue;
+        }
+
+        let status = format!(
+            "Failing: {}",
+            unhealthy
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        if unhealthy.iter().all(|(_, since)| now - **since < timeout) {
+            notify(&[NotifyState::Watchdog, NotifyState::Status(&status)]);
+        } else {
+            notify(&[NotifyState::Status(&status)]);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Fifo, Origin, Sink, Source};
+use crate::sink::MAX_LATENCY;
+
+/// Supported range of channels for pipes
+const CHANNELS: std::ops::RangeInclusive<u16> = 1..=8;
+
+/// Supported range of sample rates for pipes
+const RATES: std::ops::RangeInclusive<u32> = 8000..=192000;
+
+/// A semantic problem found in the config, with its file, line and column if known.
+#[derive(Debug)]
+pub struct Problem {
+    pub location: Option<(PathBuf, usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match &self.location {
+            Some((path, line, column)) => {
+                write!(
+                    f,
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    line,
+                    column,
+                    self.message
+                )
+            }
+            None => write!(f, "{}", self.message),
+        };
+    }
+}
+
+/// Checks the config for problems which can not be expressed by its structure.
+///
+/// The raw text of the files given by `origin` is used to locate the problems.
+pub fn validate(config: &Config, origin: &Origin) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut problem = |section: &str, index: usize, field: &str, message: String| {
+        let (file, index) = match section {
+            "outputs" => origin.outputs[index],
+            "sources" => origin.sources[index],
+            _ => (0, index),
+        };
+
+        let (path, text) = &origin.files[file];
+
+        problems.push(Problem {
+            location: locate(text, section, index, field)
+                .map(|(line, column)| (path.clone(), line, column)),
+            message,
+        });
+    };
+
+    let mut names = HashMap::new();
+    for (i, output) in config.outputs.iter().enumerate() {
+        if let Some(first) = names.insert(&output.name, i) {
+            problem(
+                "outputs",
+                i,
+                "name",
+                format!(
+                    "Output {}: name already used by output #{}",
+                    output.name,
+                    first + 1
+                ),
+            );
+        }
+    }
+
+    let mut names = HashMap::new();
+    for (i, source) in config.sources.iter().enumerate() {
+        if let Some(first) = names.insert(&source.name, i) {
+            problem(
+                "sources",
+                i,
+                "name",
+                format!(
+                    "Source {}: name already used by source #{}",
+                    source.name,
+                    first + 1
+                ),
+            );
+        }
+    }
+
+    for (i, output) in config.outputs.iter().enumerate() {
+        if output.volume > 100 {
+            problem(
+                "outputs",
+                i,
+                "volume",
+                format!("Output {}: volume must be a percentage", output.name),
+            );
+        }
+
+        if output.latency > MAX_LATENCY {
+            problem(
+                "outputs",
+                i,
+                "latency",
+                format!(
+                    "Output {}: latency exceeds maximum of {}ms",
+                    output.name, MAX_LATENCY
+                ),
+            );
+        }
+
+        if let Some(source) = &output.source {
+            if !config.sources.iter().any(|s| &s.name == source) {
+                problem(
+                    "outputs",
+                    i,
+                    "source",
+                    format!("Output {}: unknown source {}", output.name, source),
+                );
+            }
+        }
+
+        if let Sink::Pipe(pipe) = &output.sink {
+            if !CHANNELS.contains(&pipe.channels) {
+                problem(
+                    "outputs",
+                    i,
+                    "channels",
+                    format!(
+                        "Output {}: channels must be between {} and {}",
+                        output.name,
+                        CHANNELS.start(),
+                        CHANNELS.end()
+                    ),
+                );
+            }
+
+            if !RATES.contains(&pipe.rate) {
+                problem(
+                    "outputs",
+                    i,
+                    "rate",
+                    format!(
+                        "Output {}: rate must be between {} and {}",
+                        output.name,
+                        RATES.start(),
+                        RATES.end()
+                    ),
+                );
+            }
+        }
+    }
+
+    for (i, source) in config.sources.iter().enumerate() {
+        if let Source::Pipe(pipe) = &source.value {
+            if !CHANNELS.contains(&pipe.channels) {
+                problem(
+                    "sources",
+                    i,
+                    "channels",
+                    format!(
+                        "Source {}: channels must be between {} and {}",
+                        source.name,
+                        CHANNELS.start(),
+                        CHANNELS.end()
+                    ),
+                );
+            }
+        }
+    }
+
+    // Each FIFO can only be used by a single pipe
+    let fifos =
+        config
+            .outputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, output)| match &output.sink {
+                Sink::Pipe(pipe) => Some(("outputs", i, &output.name, &pipe.fifo)),
+                _ => None,
+            })
+            .chain(config.sources.iter().enumerate().filter_map(
+                |(i, source)| match &source.value {
+                    Source::Pipe(pipe) => Some(("sources", i, &source.name, &pipe.fifo)),
+                    _ => None,
+                },
+            ));
+
+    let mut paths = HashMap::<&Path, &str>::new();
+    for (section, i, name, Fifo { path, .. }) in fifos {
+        if let Some(first) = paths.insert(path, name) {
+            problem(
+                section,
+                i,
+                "path",
+                format!(
+                    "{}: path {} already used by {}",
+                    name,
+                    path.display(),
+                    first
+                ),
+            );
+        }
+    }
+
+    if config.control.listen.is_empty() {
+        problem(
+            "control",
+            0,
+            "listen",
+            "Control: at least one listen address is required".to_string(),
+        );
+    }
+
+    problems.sort_by(|a, b| a.location.cmp(&b.location));
+
+    return problems;
+}
+
+/// Finds the line and column of a field of the `index`th entry of a top-level list.
+///
+/// This is a best effort search on the raw text for block style YAML. Falls back to the start of
+/// the entry or the list if the field can not be found.
+fn locate(text: &str, section: &str, index: usize, field: &str) -> Option<(usize, usize)> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line))
+        .filter(|(_, line)| {
+            let trimmed = line.trim_start();
+            return !trimmed.is_empty() && !trimmed.starts_with('#');
+        })
+        .skip_while(|(_, line)| !line.starts_with(&format!("{}:", section)));
+
+    let (section_line, _) = lines.next()?;
+
+    // Entries of the list start with a dash at the same indentation
+    let mut entry = None;
+    let mut count = 0;
+    let mut indent = None;
+
+    for (n, line) in lines {
+        let column = line.len() - line.trim_start().len();
+        let content = line.trim_start();
+
+        // Reached the next top-level key
+        if column == 0 && !content.starts_with('-') {
+            break;
+        }
+
+        if content.starts_with('-') && indent.is_none_or(|indent| indent == column) {
+            indent = Some(column);
+
+            if count == index {
+                entry = Some((n, column + 1));
+            } else if entry.is_some() {
+                break;
+            }
+
+            count += 1;
+        }
+
+        if entry.is_some() {
+            let key = content.trim_start_matches('-').trim_start();
+            if key.starts_with(&format!("{}:", field)) {
+                return Some((n, line.len() - key.len() + 1));
+            }
+        }
+    }
+
+    return entry.or(Some((section_line, 1)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str) -> Vec<String> {
+        let config: Config = serde_yaml::from_str(text).expect("valid config");
+
+        let origin = Origin {
+            files: vec![(PathBuf::from("test.conf"), text.to_string())],
+            outputs: (0..config.outputs.len()).map(|i| (0, i)).collect(),
+            sources: (0..config.sources.len()).map(|i| (0, i)).collect(),
+        };
+
+        return validate(&config, &origin)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect();
+    }
+
+    #[test]
+    fn locate_field() {
+        let text = "\
+# Comment
+outputs:
+  - name: a
+    type: pipe
+    path: /a
+
+  # Comment
+  - name: b
+    type: pipe
+    path: /b
+sources: []
+";
+
+        assert_eq!(locate(text, "outputs", 0, "path"), Some((5, 5)));
+        assert_eq!(locate(text, "outputs", 1, "name"), Some((8, 5)));
+        assert_eq!(locate(text, "outputs", 1, "path"), Some((10, 5)));
+
+        // Falls back to the entry and the list
+        assert_eq!(locate(text, "outputs", 1, "latency"), Some((8, 3)));
+        assert_eq!(locate(text, "outputs", 2, "name"), Some((2, 1)));
+        assert_eq!(locate(text, "control", 0, "listen"), None);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let problems = check(
+            "\
+outputs:
+  - name: a
+    type: pipe
+    path: /a
+  - name: a
+    type: pipe
+    path: /b
+sources:
+  - name: a
+    type: pipe
+    path: /c
+  - name: b
+    type: pipe
+    path: /d
+  - name: b
+    type: pipe
+    path: /e
+",
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                "test.conf:5:5: Output a: name already used by output #1",
+                "test.conf:15:5: Source b: name already used by source #2",
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_fifo() {
+        let problems = check(
+            "\
+outputs:
+  - name: a
+    type: pipe
+    path: /fifo
+sources:
+  - name: b
+    type: pipe
+    path: /other
+  - name: c
+    type: pipe
+    path: /fifo
+",
+        );
+
+        assert_eq!(
+            problems,
+            vec!["test.conf:11:5: c: path /fifo already used by a"]
+        );
+    }
+}